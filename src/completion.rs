@@ -0,0 +1,673 @@
+//! Completion of the names that are visible at the cursor, of the fields
+//! of objects after a `.` and of import paths.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use jrsonnet_parser::{BindSpec, Expr, Member, ObjBody};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Documentation, InsertTextFormat,
+    MarkupContent, MarkupKind, Position, Range, TextEdit, Url,
+};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+    evaluate,
+    fields::{Expression, Resolver},
+    files::Files,
+    formatter, hover,
+    scope::{self, Analysis, Kind},
+    stdlib, utils,
+};
+
+pub fn complete(files: &Files, uri: &Url, position: Position) -> Vec<CompletionItem> {
+    let document = match files.get(uri) {
+        Some(document) => document,
+        None => return vec![],
+    };
+    let offset = utils::position_to_offset(&document.text, position);
+    if let Some((keyword, path_start)) = import_at(&document, offset) {
+        return import_paths(
+            files,
+            uri,
+            &document.text[path_start..offset],
+            keyword == "importstr",
+            position,
+        );
+    }
+    if in_literal(&document, offset) {
+        return vec![];
+    }
+    let text = &document.text[..offset];
+    let word_start = text
+        .trim_end_matches(|c: char| c == '_' || c.is_ascii_alphanumeric())
+        .len();
+    if let Some(target) = text[..word_start].trim_end().strip_suffix('.') {
+        let dot = target.len();
+        let target = target.trim_end();
+        let target_start = target
+            .trim_end_matches(|c: char| c == '_' || c.is_ascii_alphanumeric())
+            .len();
+        if &target[target_start..] == "std" && !target[..target_start].ends_with('.') {
+            return stdlib();
+        }
+        let root = Expression {
+            uri: uri.clone(),
+            expr: document.ast.clone(),
+            document: document.clone(),
+        };
+        return members(files, &root, target.len(), dot, offset);
+    }
+    names(&document, offset)
+}
+
+/// The fields of the object whose expression ends at `target_end`, visible
+/// ones first. Names that are not identifiers are completed to `['name']`,
+/// replacing the `.` at `dot` and what follows up to `offset`.
+fn members(
+    files: &Files,
+    root: &Expression,
+    target_end: usize,
+    dot: usize,
+    offset: usize,
+) -> Vec<CompletionItem> {
+    // The outermost expression ending there is the whole target, `a.b`
+    // rather than `b`.
+    let target = match cst::descendants(&root.expr)
+        .into_iter()
+        .filter(|expr| scope::end(expr) == target_end && scope::start(expr) < target_end)
+        .min_by_key(|expr| scope::start(expr))
+    {
+        Some(target) => root.with(target.clone()),
+        None => return vec![],
+    };
+    let resolver = Resolver::new(files);
+    let fields = match resolver.object(&target) {
+        Some(object) => {
+            let mut names: Vec<String> = vec![];
+            for field in object.fields() {
+                if !names.iter().any(|name| name == field.name()) {
+                    names.push(field.name().to_string());
+                }
+            }
+            names
+                .into_iter()
+                .map(|name| {
+                    let kind = object
+                        .defining(&name)
+                        .pop()
+                        .and_then(|field| resolver.field_kind(&field));
+                    (name.clone(), object.hidden(&name), kind.map(str::to_string))
+                })
+                .collect()
+        }
+        None => evaluated_fields(files, root, &target).unwrap_or_default(),
+    };
+    let (visible, hidden): (Vec<_>, Vec<_>) =
+        fields.into_iter().partition(|(_, hidden, _)| !hidden);
+    let range = utils::offset_range_to_range(&root.document.text, dot, offset);
+    visible
+        .into_iter()
+        .chain(hidden)
+        .map(|(name, hidden, kind)| {
+            let detail = kind.clone().unwrap_or_else(|| "field".to_string());
+            let mut item = CompletionItem {
+                kind: Some(match kind.as_deref() {
+                    Some("function") => CompletionItemKind::Method,
+                    _ => CompletionItemKind::Field,
+                }),
+                detail: Some(if hidden {
+                    format!("{} (hidden)", detail)
+                } else {
+                    detail
+                }),
+                sort_text: Some(format!("{}{}", if hidden { 1 } else { 0 }, name)),
+                label: name.clone(),
+                ..CompletionItem::default()
+            };
+            if !utils::is_identifier(&name) {
+                let access = format!("[{}]", formatter::quote(&name));
+                item.filter_text = Some(format!(".{}", name));
+                item.text_edit = Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: access.clone(),
+                }));
+                item.label = access;
+            }
+            item
+        })
+        .collect()
+}
+
+/// The fields of a computed object, by evaluating the target together with
+/// the locals visible to it. The locals are repeated as they are written: a
+/// `local` chain as one statement, so they can still refer to each other,
+/// and the locals of an object inside an object of their own. `self` and `$`
+/// become accesses into the document, as for the values shown on hover.
+fn evaluated_fields(
+    files: &Files,
+    root: &Expression,
+    target: &Expression,
+) -> Option<Vec<(String, bool, Option<String>)>> {
+    let document = &root.document;
+    let path = root.uri.to_file_path().ok()?;
+    let start = scope::start(&target.expr);
+    let analysis = scope::analyze_at(document, start);
+    let mut code = format!(
+        "local __top = import {};\n",
+        formatter::quote(path.to_str()?)
+    );
+    let mut closing = String::new();
+    for group in local_groups(document, &analysis) {
+        let locals = group.locals;
+        if group.in_object {
+            code.push_str("{\n");
+            for (start, end) in locals {
+                code.push_str(&format!(
+                    "  local {},\n",
+                    hover::rewrite(document, start, end)?
+                ));
+            }
+            code.push_str("  __value::\n");
+            closing.insert_str(0, "\n}.__value");
+        } else {
+            let (start, end) = (locals[0].0, locals[locals.len() - 1].1);
+            code.push_str(&format!(
+                "local {};\n",
+                hover::rewrite(document, start, end)?
+            ));
+        }
+    }
+    code.push_str(&format!(
+        "local __target = ({});\n\
+         {{\n  visible: std.objectFields(__target),\n  \
+         types: {{ [name]: std.type(__target[name]) for name in std.objectFieldsAll(__target) }},\n}}",
+        hover::rewrite(document, start, scope::end(&target.expr))?
+    ));
+    code.push_str(&closing);
+    let result = evaluate::snippet(files, &path, &code)?;
+    let visible: Vec<&str> = result["visible"]
+        .as_array()?
+        .iter()
+        .filter_map(|name| name.as_str())
+        .collect();
+    let types = result["types"].as_object()?;
+    Some(
+        types
+            .iter()
+            .map(|(name, kind)| {
+                (
+                    name.clone(),
+                    !visible.contains(&name.as_str()),
+                    kind.as_str().map(str::to_string),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Visible locals that are bound together, by one `local` expression or in
+/// one object.
+#[derive(Debug, PartialEq)]
+struct LocalGroup {
+    in_object: bool,
+    /// The byte range of each local, from its name to the end of its value.
+    locals: Vec<(usize, usize)>,
+}
+
+/// The locals visible in `analysis`, outermost first.
+fn local_groups(document: &Document, analysis: &Analysis) -> Vec<LocalGroup> {
+    let mut binders: Vec<(bool, Vec<&BindSpec>)> = vec![];
+    for expr in cst::descendants(&document.ast) {
+        match &*expr.0 {
+            Expr::LocalExpr(binds, _) => binders.push((false, binds.iter().collect())),
+            Expr::Obj(ObjBody::MemberList(members))
+            | Expr::ObjExtend(_, ObjBody::MemberList(members)) => {
+                let binds = members.iter().filter_map(|member| match member {
+                    Member::BindStmt(bind) => Some(bind),
+                    _ => None,
+                });
+                binders.push((true, binds.collect()));
+            }
+            Expr::Obj(ObjBody::ObjComp(comp)) | Expr::ObjExtend(_, ObjBody::ObjComp(comp)) => {
+                let binds = comp.pre_locals.iter().chain(&comp.post_locals);
+                binders.push((true, binds.collect()));
+            }
+            _ => {}
+        }
+    }
+    let mut groups: Vec<LocalGroup> = vec![];
+    let mut last_binder = None;
+    for definition in &analysis.visible {
+        let definition = &analysis.definitions[*definition];
+        let value = match (definition.kind, &definition.value) {
+            (Kind::Local, Some(value)) => value,
+            _ => continue,
+        };
+        let binder = match binders
+            .iter()
+            .position(|(_, binds)| binds.iter().any(|bind| Rc::ptr_eq(&bind.value.0, &value.0)))
+        {
+            Some(binder) => binder,
+            None => continue,
+        };
+        let local = (definition.start, scope::end(value));
+        match groups.last_mut() {
+            Some(group) if last_binder == Some(binder) => group.locals.push(local),
+            _ => groups.push(LocalGroup {
+                in_object: binders[binder].0,
+                locals: vec![local],
+            }),
+        }
+        last_binder = Some(binder);
+    }
+    groups
+}
+
+fn stdlib() -> Vec<CompletionItem> {
+    stdlib::functions()
+        .into_iter()
+        .map(|(name, function)| match function {
+            Some(function) => CompletionItem {
+                label: name,
+                kind: Some(match function.params {
+                    Some(_) => CompletionItemKind::Function,
+                    None => CompletionItemKind::Field,
+                }),
+                detail: Some(function.signature()),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: function.doc.to_string(),
+                })),
+                insert_text: Some(function.snippet()),
+                insert_text_format: Some(InsertTextFormat::Snippet),
+                ..CompletionItem::default()
+            },
+            None => CompletionItem {
+                label: name,
+                kind: Some(CompletionItemKind::Function),
+                ..CompletionItem::default()
+            },
+        })
+        .collect()
+}
+
+/// The keyword of the `import` or `importstr` whose path string contains
+/// `offset`, and the offset the path starts at.
+fn import_at(document: &Document, offset: usize) -> Option<(&str, usize)> {
+    let tokens = &document.cst.tokens;
+    let index = tokens
+        .iter()
+        .position(|t| t.start < offset && offset <= t.end)?;
+    let string = tokens[index];
+    let text = &document.text;
+    let quoted = match string.kind {
+        TokenKind::String => offset < string.end,
+        // Not terminated yet.
+        TokenKind::Unknown => true,
+        _ => false,
+    };
+    if !quoted || !matches!(text[string.start..].chars().next(), Some('\'' | '"')) {
+        return None;
+    }
+    let keyword = tokens[..index]
+        .iter()
+        .rev()
+        .find(|t| !t.kind.is_trivia())
+        .filter(|t| t.kind == TokenKind::Keyword)?
+        .text(text);
+    match keyword {
+        "import" | "importstr" => Some((keyword, string.start + 1)),
+        _ => None,
+    }
+}
+
+/// The files and directories `path` can be completed to, looked up like
+/// the import would be. Jsonnet files come first for `import`.
+fn import_paths(
+    files: &Files,
+    uri: &Url,
+    path: &str,
+    importstr: bool,
+    position: Position,
+) -> Vec<CompletionItem> {
+    let (dir, partial) = match path.rfind('/') {
+        Some(slash) => (&path[..=slash], &path[slash + 1..]),
+        None => ("", path),
+    };
+    let dirs = if Path::new(dir).is_absolute() {
+        vec![PathBuf::from(dir)]
+    } else {
+        files
+            .search_dirs(uri)
+            .into_iter()
+            .map(|search_dir| search_dir.join(dir))
+            .collect()
+    };
+    // Only the last path segment is replaced.
+    let range = Range {
+        start: Position {
+            character: position
+                .character
+                .saturating_sub(partial.encode_utf16().count() as u32),
+            ..position
+        },
+        end: position,
+    };
+    let mut seen = HashSet::new();
+    let mut items = vec![];
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if name.starts_with('.') && !partial.starts_with('.') {
+                continue;
+            }
+            let is_dir = entry.path().is_dir();
+            let label = if is_dir { format!("{}/", name) } else { name };
+            if !seen.insert(label.clone()) {
+                continue;
+            }
+            let jsonnet = label.ends_with(".libsonnet") || label.ends_with(".jsonnet");
+            let (kind, rank) = match (is_dir, importstr || jsonnet) {
+                (true, _) => (CompletionItemKind::Folder, 1),
+                (false, true) => (CompletionItemKind::File, 0),
+                (false, false) => (CompletionItemKind::File, 2),
+            };
+            items.push(CompletionItem {
+                kind: Some(kind),
+                sort_text: Some(format!("{}{}", rank, label)),
+                filter_text: Some(label.clone()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: label.clone(),
+                })),
+                label,
+                ..CompletionItem::default()
+            });
+        }
+    }
+    items.sort_by(|a, b| a.sort_text.cmp(&b.sort_text));
+    items
+}
+
+/// Whether `offset` is inside a string or a comment.
+fn in_literal(document: &Document, offset: usize) -> bool {
+    document
+        .cst
+        .tokens
+        .iter()
+        .find(|t| t.start < offset && offset <= t.end)
+        .is_some_and(|t| match t.kind {
+            TokenKind::LineComment | TokenKind::BlockComment | TokenKind::Unknown => true,
+            TokenKind::String => offset < t.end,
+            _ => false,
+        })
+}
+
+fn names(document: &Document, offset: usize) -> Vec<CompletionItem> {
+    let mut items = vec![];
+    let analysis = scope::analyze_at(document, offset);
+    let mut seen = HashSet::new();
+    // Innermost first, so shadowed bindings are left out.
+    for definition in analysis.visible.iter().rev() {
+        let definition = &analysis.definitions[*definition];
+        if !seen.insert(definition.name.as_str()) {
+            continue;
+        }
+        let (kind, detail) = match (definition.kind, &definition.params) {
+            (Kind::Local, Some(params)) => {
+                let params: Vec<&str> = params.iter().map(|param| &*param.0).collect();
+                (
+                    CompletionItemKind::Function,
+                    format!("local {}({})", definition.name, params.join(", ")),
+                )
+            }
+            (Kind::Local, None) => (CompletionItemKind::Variable, "local".to_string()),
+            (Kind::Parameter, _) => (CompletionItemKind::Variable, "parameter".to_string()),
+            (Kind::ForVariable, _) => (CompletionItemKind::Variable, "for variable".to_string()),
+        };
+        items.push(CompletionItem {
+            label: definition.name.clone(),
+            kind: Some(kind),
+            detail: Some(detail),
+            sort_text: Some(format!("0{}", definition.name)),
+            ..CompletionItem::default()
+        });
+    }
+
+    let in_object = cst::path_at(&document.ast, offset)
+        .iter()
+        .any(|expr| match &*expr.0 {
+            Expr::Obj(_) => true,
+            Expr::ObjExtend(base, _) => offset >= scope::end(base),
+            _ => false,
+        });
+    let keywords = utils::KEYWORDS
+        .iter()
+        .filter(|keyword| in_object || !matches!(**keyword, "self" | "super"))
+        .chain(if in_object { Some(&"$") } else { None });
+    for keyword in keywords {
+        items.push(CompletionItem {
+            label: keyword.to_string(),
+            kind: Some(CompletionItemKind::Keyword),
+            sort_text: Some(format!("1{}", keyword)),
+            ..CompletionItem::default()
+        });
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::{CompletionItemKind, Url};
+
+    use crate::{document::Document, files::Files, scope, testing::TempDir, utils};
+
+    /// Completes at `|` in `code`.
+    fn labels(code: &str) -> Vec<String> {
+        let offset = code.find('|').unwrap();
+        let code = code.replacen('|', "", 1);
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.clone()));
+        let position = utils::offset_to_position(&code, offset);
+        super::complete(&files, &uri, position)
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    }
+
+    #[test]
+    fn bindings() {
+        let labels = labels("local a = 1;\nlocal f(p) = [v + | for v in [p]];\nf(a)");
+        assert_eq!(labels[..4], ["v", "p", "f", "a"]);
+        assert!(labels.contains(&"local".to_string()));
+        assert!(!labels.contains(&"self".to_string()));
+    }
+
+    #[test]
+    fn shadowing_and_objects() {
+        let labels = labels("local a = 1;\n{\n  local a = 2,\n  local b = 3,\n  c: a + |\n}");
+        assert_eq!(labels[..2], ["b", "a"]);
+        assert_eq!(labels.iter().filter(|label| *label == "a").count(), 1);
+        for keyword in &["self", "super", "$"] {
+            assert!(labels.contains(&keyword.to_string()));
+        }
+    }
+
+    #[test]
+    fn partial_names() {
+        assert_eq!(labels("local abc = 1; ab|")[0], "abc");
+        assert_eq!(labels("local abc = 1; abc + |")[0], "abc");
+        assert!(labels("local a = 1; 'a|'").is_empty());
+        assert!(labels("local a = 1; // a|").is_empty());
+    }
+
+    #[test]
+    fn stdlib() {
+        let functions = labels("local a = std.|");
+        for function in &["length", "map", "objectFieldsAll", "manifestJsonEx"] {
+            assert!(functions.contains(&function.to_string()));
+        }
+        assert!(!functions.contains(&"a".to_string()));
+        assert!(labels("local a = x.std.|").is_empty());
+    }
+
+    #[test]
+    fn members() {
+        let code = "{\n  a: 1,\n  b:: 'x',\n  f(x):: x,\n  c: { d: self.| },\n  e: $.|\n}";
+        assert_eq!(labels(&code.replacen("$.|", "$.", 1)), ["d"]);
+        assert_eq!(
+            labels(&code.replacen("self.|", "self.", 1)),
+            ["a", "c", "e", "b", "f"]
+        );
+
+        let items = {
+            let code = "{ a:: 1 } + { a: 2, b: [] } + { c: super.| }";
+            let offset = code.find('|').unwrap();
+            let code = code.replacen('|', "", 1);
+            let uri = Url::parse("file:///test.jsonnet").unwrap();
+            let mut files = Files::new(vec![]);
+            files.insert(uri.clone(), Document::new(&uri, code.clone()));
+            super::complete(&files, &uri, utils::offset_to_position(&code, offset))
+        };
+        let items: Vec<_> = items
+            .into_iter()
+            .map(|item| (item.label, item.detail.unwrap(), item.sort_text.unwrap()))
+            .collect();
+        let item = |label: &str, detail: &str, sort_text: &str| {
+            (label.to_string(), detail.to_string(), sort_text.to_string())
+        };
+        assert_eq!(
+            items,
+            [item("b", "array", "0b"), item("a", "number (hidden)", "1a")]
+        );
+    }
+
+    #[test]
+    fn local_groups() {
+        let code = "local a = b, b = 1;\nlocal f(x) = x;\n{\n  local c = self.d,\n  d: a,\n  local e = 2,\n  g: [c for i in [1]],\n}";
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let document = Document::new(&uri, code.to_string());
+        let analysis = scope::analyze_at(&document, code.find("c for").unwrap());
+        let groups: Vec<(bool, Vec<&str>)> = super::local_groups(&document, &analysis)
+            .into_iter()
+            .map(|group| {
+                let locals = group.locals.iter().map(|&(start, end)| &code[start..end]);
+                (group.in_object, locals.collect())
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (false, vec!["a = b", "b = 1"]),
+                (false, vec!["f(x) = x"]),
+                (true, vec!["c = self.d", "e = 2"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluated_members() {
+        let root = TempDir::new("evaluated");
+        // Locals that refer to each other, which only work as one chain.
+        let code = "local a = { x: b.y }, b = { y: 1 };\nlocal o = std.mergePatch(a, b);\no.";
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let labels: Vec<String> =
+            super::complete(&files, &uri, utils::offset_to_position(code, code.len()))
+                .into_iter()
+                .map(|item| item.label)
+                .collect();
+        assert_eq!(labels, ["x", "y"]);
+
+        // `self` is the document's object, not the one the locals are
+        // repeated in.
+        let code = "{\n  a: { x: 1 },\n  local o = std.mergePatch(self.a, {}),\n  b: o.x,\n}";
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let offset = code.find("o.x").unwrap() + 2;
+        let labels: Vec<String> =
+            super::complete(&files, &uri, utils::offset_to_position(code, offset))
+                .into_iter()
+                .map(|item| item.label)
+                .collect();
+        assert_eq!(labels, ["x"]);
+    }
+
+    #[test]
+    fn imported_members() {
+        let root = TempDir::new("completion");
+        fs::write(
+            root.join("lib.libsonnet"),
+            "{ new(name):: { name: name }, version: '1', 'not id': 2 }",
+        )
+        .unwrap();
+
+        let code = "local lib = import 'lib.libsonnet';\nlib.";
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let items = super::complete(&files, &uri, utils::offset_to_position(code, code.len()));
+        let items: Vec<_> = items
+            .iter()
+            .map(|item| (item.label.as_str(), item.kind.unwrap()))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("version", CompletionItemKind::Field),
+                ("['not id']", CompletionItemKind::Field),
+                ("new", CompletionItemKind::Method)
+            ]
+        );
+    }
+
+    #[test]
+    fn import_paths() {
+        let root = TempDir::new("paths");
+        fs::create_dir_all(root.join("app/lib")).unwrap();
+        fs::create_dir_all(root.join("vendor/k")).unwrap();
+        fs::write(root.join("app/data.txt"), "").unwrap();
+        fs::write(root.join("app/util.libsonnet"), "{}").unwrap();
+        fs::write(root.join("vendor/k/k.libsonnet"), "{}").unwrap();
+
+        let uri = Url::from_file_path(root.join("app/main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![root.join("vendor")]);
+        let mut complete = |code: &str| -> Vec<String> {
+            let offset = code.find('|').unwrap();
+            let code = code.replacen('|', "", 1);
+            files.insert(uri.clone(), Document::new(&uri, code.clone()));
+            let position = utils::offset_to_position(&code, offset);
+            super::complete(&files, &uri, position)
+                .into_iter()
+                .map(|item| item.label)
+                .collect()
+        };
+
+        assert_eq!(
+            complete("import '|'"),
+            ["util.libsonnet", "k/", "lib/", "data.txt"]
+        );
+        assert_eq!(
+            complete("importstr 'u|"),
+            ["data.txt", "util.libsonnet", "k/", "lib/"]
+        );
+        assert_eq!(complete("import 'k/|'"), ["k.libsonnet"]);
+        assert!(complete("local a = 'k/|'; a").is_empty());
+    }
+}
@@ -0,0 +1,525 @@
+//! Lossless concrete syntax tree.
+//!
+//! The tokens produced by [`lex`] cover every byte of the source, including
+//! whitespace and comments, and the [`Node`]s built on top of them point back
+//! to the `LocExpr` they were derived from. Features that rewrite code use
+//! this to keep comments the AST does not know about.
+
+use std::{ops::Range, rc::Rc};
+
+use jrsonnet_parser::{CompSpec, Expr, FieldName, LocExpr, Member, ObjBody, ParamsDesc};
+
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    /// `// ...` or `# ...` up to, but not including, the newline.
+    LineComment,
+    /// `/* ... */`
+    BlockComment,
+    Ident,
+    Keyword,
+    Number,
+    /// Any string literal, including verbatim strings and text blocks.
+    String,
+    Symbol,
+    /// A character that does not start any token, or an unterminated
+    /// string or comment.
+    Unknown,
+}
+
+impl TokenKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment
+        )
+    }
+
+    pub fn is_comment(self) -> bool {
+        matches!(self, TokenKind::LineComment | TokenKind::BlockComment)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    pub fn text<'a>(&self, code: &'a str) -> &'a str {
+        &code[self.start..self.end]
+    }
+}
+
+const SYMBOLS: &[&str] = &[
+    ":::", "::", "==", "!=", "<=", ">=", "<<", ">>", "&&", "||", "{", "}", "[", "]", "(", ")", ",",
+    ".", ";", ":", "+", "-", "*", "/", "%", "&", "|", "^", "!", "~", "<", ">", "=", "$",
+];
+
+/// Splits `code` into tokens. Concatenating the text of all tokens yields
+/// `code` again.
+pub fn lex(code: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut lexer = Lexer { code, pos: 0 };
+    while lexer.pos < code.len() {
+        let start = lexer.pos;
+        let kind = lexer.next_kind();
+        tokens.push(Token {
+            kind,
+            start,
+            end: lexer.pos,
+        });
+    }
+    tokens
+}
+
+struct Lexer<'a> {
+    code: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.code[self.pos..]
+    }
+
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) {
+        let len = self
+            .rest()
+            .char_indices()
+            .find(|(_, c)| !f(*c))
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| self.rest().len());
+        self.pos += len;
+    }
+
+    fn eat_line(&mut self) {
+        self.pos += self.rest().find('\n').unwrap_or_else(|| self.rest().len());
+    }
+
+    fn next_kind(&mut self) -> TokenKind {
+        let start = self.pos;
+        let rest = self.rest();
+        let c = rest.chars().next().expect("lexer is not at the end");
+        if c.is_whitespace() {
+            self.eat_while(char::is_whitespace);
+            TokenKind::Whitespace
+        } else if rest.starts_with("//") || c == '#' {
+            self.eat_line();
+            TokenKind::LineComment
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(i) => {
+                    self.pos += i + 4;
+                    TokenKind::BlockComment
+                }
+                None => {
+                    self.pos = self.code.len();
+                    TokenKind::Unknown
+                }
+            }
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            self.eat_while(|c| c == '_' || c.is_ascii_alphanumeric());
+            if utils::KEYWORDS.contains(&&self.code[start..self.pos]) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Ident
+            }
+        } else if c.is_ascii_digit() {
+            self.number();
+            TokenKind::Number
+        } else if c == '"' || c == '\'' {
+            self.pos += 1;
+            self.quoted(c)
+        } else if c == '@' && (rest[1..].starts_with('"') || rest[1..].starts_with('\'')) {
+            let quote = rest[1..].chars().next().unwrap();
+            self.pos += 2;
+            self.verbatim(quote)
+        } else if rest.starts_with("|||") {
+            self.text_block()
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            self.pos += symbol.len();
+            TokenKind::Symbol
+        } else {
+            self.pos += c.len_utf8();
+            TokenKind::Unknown
+        }
+    }
+
+    fn number(&mut self) {
+        self.eat_while(|c| c.is_ascii_digit());
+        let rest = self.rest();
+        if rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.pos += 1;
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+        let rest = self.rest();
+        if rest.starts_with(['e', 'E']) {
+            let sign = if rest[1..].starts_with(['+', '-']) {
+                1
+            } else {
+                0
+            };
+            if rest[1 + sign..].starts_with(|c: char| c.is_ascii_digit()) {
+                self.pos += 1 + sign;
+                self.eat_while(|c| c.is_ascii_digit());
+            }
+        }
+    }
+
+    fn quoted(&mut self, quote: char) -> TokenKind {
+        let mut escaped = false;
+        for (i, c) in self.rest().char_indices() {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                self.pos += i + 1;
+                return TokenKind::String;
+            }
+        }
+        self.pos = self.code.len();
+        TokenKind::Unknown
+    }
+
+    fn verbatim(&mut self, quote: char) -> TokenKind {
+        loop {
+            match self.rest().find(quote) {
+                Some(i) => {
+                    self.pos += i + 1;
+                    // A doubled quote is an escaped quote.
+                    if !self.rest().starts_with(quote) {
+                        return TokenKind::String;
+                    }
+                    self.pos += 1;
+                }
+                None => {
+                    self.pos = self.code.len();
+                    return TokenKind::Unknown;
+                }
+            }
+        }
+    }
+
+    fn text_block(&mut self) -> TokenKind {
+        self.pos += 3;
+        self.eat_line();
+        if self.pos < self.code.len() {
+            self.pos += 1;
+        }
+        // The indentation of the first non-empty line applies to the whole
+        // block, which is terminated by `|||` on a less indented line.
+        let indent = self
+            .rest()
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .unwrap_or(0);
+        while self.pos < self.code.len() {
+            let line = self.rest().split('\n').next().unwrap_or("");
+            let content = line.trim_start();
+            let line_indent = line.len() - content.len();
+            if content.starts_with("|||") && line_indent < indent.max(1) {
+                self.pos += line_indent + 3;
+                return TokenKind::String;
+            }
+            if !content.is_empty() && line_indent < indent {
+                break;
+            }
+            self.eat_line();
+            if self.pos < self.code.len() {
+                self.pos += 1;
+            }
+        }
+        TokenKind::Unknown
+    }
+}
+
+/// A syntax node that owns a contiguous run of tokens and corresponds to one
+/// located expression of the AST.
+#[derive(Debug)]
+pub struct Node {
+    pub expr: LocExpr,
+    /// Indices into [`Tree::tokens`], without leading or trailing trivia.
+    pub tokens: Range<usize>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug)]
+pub struct Tree {
+    pub tokens: Vec<Token>,
+    pub root: Option<Node>,
+}
+
+impl Tree {
+    pub fn new(tokens: Vec<Token>, ast: &LocExpr) -> Tree {
+        let root = build(&tokens, ast).pop();
+        Tree { tokens, root }
+    }
+
+    /// Comment tokens that lie completely within the byte range.
+    pub fn comments(&self, start: usize, end: usize) -> impl Iterator<Item = &Token> {
+        let first = self.tokens.partition_point(|t| t.start < start);
+        self.tokens[first..]
+            .iter()
+            .take_while(move |t| t.end <= end)
+            .filter(|t| t.kind.is_comment())
+    }
+
+    pub fn comment_count(&self) -> usize {
+        self.tokens.iter().filter(|t| t.kind.is_comment()).count()
+    }
+
+    /// Finds the node that was built for `expr`.
+    pub fn node_for(&self, expr: &LocExpr) -> Option<&Node> {
+        fn find<'a>(node: &'a Node, expr: &LocExpr) -> Option<&'a Node> {
+            if Rc::ptr_eq(&node.expr.0, &expr.0) {
+                return Some(node);
+            }
+            let start = expr.1.as_ref()?.1;
+            node.children
+                .iter()
+                .filter(|child| child.expr.1.as_ref().is_some_and(|l| l.1 <= start))
+                .find_map(|child| find(child, expr))
+        }
+        find(self.root.as_ref()?, expr)
+    }
+}
+
+/// Builds the nodes for `expr`. Expressions without location data do not get
+/// a node of their own, their children are returned instead.
+fn build(tokens: &[Token], expr: &LocExpr) -> Vec<Node> {
+    let mut children = vec![];
+    for child in self::children(expr) {
+        children.extend(build(tokens, child));
+    }
+    let location = match &expr.1 {
+        Some(location) => location,
+        None => return children,
+    };
+    let mut first = tokens.partition_point(|t| t.start < location.1);
+    let mut last = tokens.partition_point(|t| t.end <= location.2);
+    while first < last && tokens[first].kind.is_trivia() {
+        first += 1;
+    }
+    while last > first && tokens[last - 1].kind.is_trivia() {
+        last -= 1;
+    }
+    children.sort_by_key(|child| child.tokens.start);
+    vec![Node {
+        expr: expr.clone(),
+        tokens: first..last,
+        children,
+    }]
+}
+
+fn params_children<'a>(params: &'a Option<ParamsDesc>, out: &mut Vec<&'a LocExpr>) {
+    if let Some(params) = params {
+        out.extend(params.iter().filter_map(|p| p.1.as_ref()));
+    }
+}
+
+fn comp_specs_children<'a>(specs: &'a [CompSpec], out: &mut Vec<&'a LocExpr>) {
+    for spec in specs {
+        match spec {
+            CompSpec::IfSpec(if_spec) => out.push(&if_spec.0),
+            CompSpec::ForSpec(for_spec) => out.push(&for_spec.1),
+        }
+    }
+}
+
+fn obj_body_children<'a>(body: &'a ObjBody, out: &mut Vec<&'a LocExpr>) {
+    match body {
+        ObjBody::MemberList(members) => {
+            for member in members {
+                match member {
+                    Member::Field(field) => {
+                        if let FieldName::Dyn(name) = &field.name {
+                            out.push(name);
+                        }
+                        params_children(&field.params, out);
+                        out.push(&field.value);
+                    }
+                    Member::BindStmt(bind) => {
+                        params_children(&bind.params, out);
+                        out.push(&bind.value);
+                    }
+                    Member::AssertStmt(assert) => {
+                        out.push(&assert.0);
+                        out.extend(assert.1.as_ref());
+                    }
+                }
+            }
+        }
+        ObjBody::ObjComp(comp) => {
+            for bind in &comp.pre_locals {
+                params_children(&bind.params, out);
+                out.push(&bind.value);
+            }
+            out.push(&comp.key);
+            out.push(&comp.value);
+            for bind in &comp.post_locals {
+                params_children(&bind.params, out);
+                out.push(&bind.value);
+            }
+            comp_specs_children(&comp.compspecs, out);
+        }
+    }
+}
+
+/// The expressions from `root` down to the innermost one whose location
+/// contains `offset`, outermost first.
+pub fn path_at(root: &LocExpr, offset: usize) -> Vec<&LocExpr> {
+    let contains = |expr: &LocExpr| {
+        expr.1
+            .as_ref()
+            .is_some_and(|l| l.1 <= offset && offset <= l.2)
+    };
+    let mut path = vec![];
+    if !contains(root) {
+        return path;
+    }
+    let mut expr = root;
+    path.push(expr);
+    while let Some(child) = children(expr).into_iter().find(|child| contains(child)) {
+        path.push(child);
+        expr = child;
+    }
+    path
+}
+
+/// `expr` and all expressions below it, in source order.
+pub fn descendants(expr: &LocExpr) -> Vec<&LocExpr> {
+    fn walk<'a>(expr: &'a LocExpr, out: &mut Vec<&'a LocExpr>) {
+        out.push(expr);
+        for child in children(expr) {
+            walk(child, out);
+        }
+    }
+    let mut out = vec![];
+    walk(expr, &mut out);
+    out
+}
+
+/// The direct sub-expressions of `expr`, in source order.
+pub fn children(expr: &LocExpr) -> Vec<&LocExpr> {
+    let mut out = vec![];
+    match &*expr.0 {
+        Expr::Literal(_)
+        | Expr::Str(_)
+        | Expr::Num(_)
+        | Expr::Var(_)
+        | Expr::Import(_)
+        | Expr::ImportStr(_)
+        | Expr::Intrinsic(_) => {}
+        Expr::Arr(items) => out.extend(items),
+        Expr::ArrComp(value, specs) => {
+            out.push(value);
+            comp_specs_children(specs, &mut out);
+        }
+        Expr::Obj(body) => obj_body_children(body, &mut out),
+        Expr::ObjExtend(base, body) => {
+            out.push(base);
+            obj_body_children(body, &mut out);
+        }
+        Expr::Parened(inner) | Expr::UnaryOp(_, inner) | Expr::ErrorStmt(inner) => out.push(inner),
+        Expr::BinaryOp(left, _, right) | Expr::Index(left, right) => {
+            out.push(left);
+            out.push(right);
+        }
+        Expr::AssertExpr(assert, rest) => {
+            out.push(&assert.0);
+            out.extend(assert.1.as_ref());
+            out.push(rest);
+        }
+        Expr::LocalExpr(binds, body) => {
+            for bind in binds {
+                params_children(&bind.params, &mut out);
+                out.push(&bind.value);
+            }
+            out.push(body);
+        }
+        Expr::Apply(function, args, _) => {
+            out.push(function);
+            out.extend(args.0.iter().map(|arg| &arg.1));
+        }
+        Expr::Function(params, body) => {
+            out.extend(params.iter().filter_map(|p| p.1.as_ref()));
+            out.push(body);
+        }
+        Expr::IfElse {
+            cond,
+            cond_then,
+            cond_else,
+        } => {
+            out.push(&cond.0);
+            out.push(cond_then);
+            out.extend(cond_else.as_ref());
+        }
+        Expr::Slice(value, desc) => {
+            out.push(value);
+            out.extend(desc.start.as_ref());
+            out.extend(desc.end.as_ref());
+            out.extend(desc.step.as_ref());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lex, TokenKind};
+
+    #[test]
+    fn lex_is_lossless() {
+        let code = r#"# header
+local a = 1.5e3; // trailing
+/* block
+   comment */
+{
+  'b': @"x""y",
+  c::: |||
+    text
+    block
+  |||,
+  d: a <= 2 && !$.e,
+}
+"#;
+        let tokens = lex(code);
+        let text: String = tokens.iter().map(|t| t.text(code)).collect();
+        assert_eq!(text, code);
+
+        let comments: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.kind.is_comment())
+            .map(|t| t.text(code))
+            .collect();
+        assert_eq!(
+            comments,
+            vec!["# header", "// trailing", "/* block\n   comment */"]
+        );
+
+        let strings: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::String)
+            .map(|t| t.text(code))
+            .collect();
+        assert_eq!(
+            strings,
+            vec!["'b'", "@\"x\"\"y\"", "|||\n    text\n    block\n  |||"]
+        );
+    }
+
+    #[test]
+    fn lex_unterminated() {
+        let code = "{ a: 'abc }";
+        let tokens = lex(code);
+        assert_eq!(tokens.last().unwrap().kind, TokenKind::Unknown);
+        assert_eq!(tokens.last().unwrap().text(code), "'abc }");
+    }
+}
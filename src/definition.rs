@@ -0,0 +1,142 @@
+//! Go to definition.
+
+use std::path::Path;
+
+use jrsonnet_parser::{Expr, LocExpr};
+use lsp_types::{Location, Position, Range, Url};
+
+use crate::{
+    cst,
+    fields::{self, Expression, Resolver},
+    files::Files,
+    scope, utils,
+};
+
+pub fn find(files: &Files, uri: &Url, position: Position) -> Option<Location> {
+    let document = files.get(uri)?;
+    let offset = utils::position_to_offset(&document.text, position);
+
+    let path = cst::path_at(&document.ast, offset);
+    if let Some(path) = path.last().and_then(|expr| import_path(expr)) {
+        return files.resolve(uri, path).map(file_start);
+    }
+    if let Some((target, name)) = fields::access(&path) {
+        let target = Expression {
+            uri: uri.clone(),
+            document: document.clone(),
+            expr: target.clone(),
+        };
+        let field = Resolver::new(files).object(&target)?.lookup(name).pop()?;
+        return Some(field.location());
+    }
+
+    let analysis = scope::analyze(&document);
+    let definition = &analysis.definitions[analysis.definition_at(offset)?];
+    // Names bound to an import lead to the imported file.
+    if let Some(path) = definition
+        .value
+        .as_ref()
+        .filter(|_| definition.params.is_none())
+        .and_then(import_path)
+    {
+        if let Some(target) = files.resolve(uri, path) {
+            return Some(file_start(target));
+        }
+    }
+    Some(Location {
+        uri: uri.clone(),
+        range: utils::offset_range_to_range(&document.text, definition.start, definition.end),
+    })
+}
+
+fn import_path(expr: &LocExpr) -> Option<&Path> {
+    match &*expr.0 {
+        Expr::Import(path) | Expr::ImportStr(path) => Some(path),
+        Expr::Parened(inner) => import_path(inner),
+        _ => None,
+    }
+}
+
+fn file_start(uri: Url) -> Location {
+    Location {
+        uri,
+        range: Range::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::{Range, Url};
+
+    use crate::{document::Document, files::Files, testing::TempDir, utils};
+
+    #[test]
+    fn imports() {
+        let root = TempDir::new("definition");
+        fs::write(root.join("k.libsonnet"), "{ a: 1 }").unwrap();
+        let lib = Url::from_file_path(root.join("k.libsonnet")).unwrap();
+
+        let code = "local k = import 'k.libsonnet';\nlocal s = importstr 'missing.txt';\n[k.a, s, k { b: super.a }]";
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let find = |pattern: &str| {
+            let position = utils::offset_to_position(code, code.find(pattern).unwrap());
+            super::find(&files, &uri, position)
+        };
+
+        let location = find("k.libsonnet").unwrap();
+        assert_eq!(location.uri, lib);
+        assert_eq!(location.range, Range::default());
+        assert_eq!(find("k.a").unwrap().uri, lib);
+        assert_eq!(find("k =").unwrap().uri, lib);
+        let location = find("a, s").unwrap();
+        assert_eq!(location.uri, lib);
+        assert_eq!(location.range.start.character, 2);
+        assert_eq!(find("a }").unwrap().range, location.range);
+
+        assert_eq!(find("missing"), None);
+        // Unresolved imports fall back to the binding itself.
+        let location = find("s, k").unwrap();
+        assert_eq!(location.uri, uri);
+        assert_eq!(location.range.start.line, 1);
+    }
+
+    #[test]
+    fn fields() {
+        let code = r#"
+local base = { a: 1, nested: { x: 1 } };
+local obj = base + {
+  b: self.a,
+  nested+: { y: 2 },
+  c: $.b,
+} + { a: super.a };
+local ext = obj { d: super.b };
+[obj.nested.x, obj.nested.y, obj.c, ext.d, ext.nested.x, obj.missing]
+"#;
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let find = |pattern: &str, skip: usize| {
+            let offset = code.find(pattern).unwrap() + skip;
+            let position = utils::offset_to_position(code, offset);
+            let location = super::find(&files, &uri, position)?;
+            Some(utils::position_to_offset(code, location.range.start))
+        };
+
+        // `self` is the whole object, so `a` comes from the last layer.
+        assert_eq!(find("self.a", 5), code.find("a: super"));
+        assert_eq!(find("super.a", 6), code.find("a: 1"));
+        assert_eq!(find("$.b", 2), code.find("b: self"));
+        assert_eq!(find("super.b", 6), code.find("b: self"));
+        // `+:` keeps the fields of the object it extends.
+        assert_eq!(find("nested.x,", 7), code.find("x: 1"));
+        assert_eq!(find("nested.y", 7), code.find("y: 2"));
+        assert_eq!(find("obj.c", 4), code.find("c: $"));
+        assert_eq!(find("ext.d", 4), code.find("d: super"));
+        assert_eq!(find("ext.nested.x", 11), code.find("x: 1"));
+        assert_eq!(find("obj.missing", 4), None);
+    }
+}
@@ -0,0 +1,42 @@
+use jrsonnet_parser::{LocExpr, ParseError};
+use lsp_types::Url;
+
+use crate::{
+    cst,
+    parser::{self, SyntaxError},
+    utils,
+};
+
+/// A parsed Jsonnet file together with its source.
+pub struct Document {
+    pub text: String,
+    /// Result of the reference parser, which evaluation is based on.
+    pub parsed: Result<LocExpr, ParseError>,
+    /// Tree of the error-tolerant parser. It is available even if the
+    /// document has syntax errors, so features should work on this one.
+    pub ast: LocExpr,
+    pub errors: Vec<SyntaxError>,
+    pub cst: cst::Tree,
+}
+
+impl Document {
+    pub fn new(uri: &Url, text: String) -> Document {
+        let settings = utils::parser_settings(uri);
+        let parsed = jrsonnet_parser::parse(&text, &settings);
+        let tokens = cst::lex(&text);
+        let (ast, errors) = parser::parse(&text, &tokens, settings.file_name);
+        let cst = cst::Tree::new(tokens, &ast);
+        Document {
+            text,
+            parsed,
+            ast,
+            errors,
+            cst,
+        }
+    }
+
+    /// Returns whether both parsers accepted the document.
+    pub fn is_valid(&self) -> bool {
+        self.parsed.is_ok() && self.errors.is_empty()
+    }
+}
@@ -0,0 +1,365 @@
+//! Evaluation of Jsonnet with `jrsonnet_evaluator`, for whatever cannot be
+//! told from the syntax tree alone.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use jrsonnet_evaluator::{
+    EvaluationState, FileImportResolver, ImportResolver, LocError, Result as EvalResult, Val,
+};
+use jrsonnet_parser::IStr;
+use log::debug;
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, Position, Range, Url,
+};
+
+use crate::{document::Document, files::Files, links, utils};
+
+/// Manifested values longer than this are cut off in hovers.
+const MAX_VALUE_LINES: usize = 30;
+
+/// An evaluation state with the standard library that resolves imports
+/// like [`Files::resolve`].
+pub fn state(files: &Files) -> EvaluationState {
+    let state = EvaluationState::default();
+    state.with_stdlib();
+    let open = files
+        .opened()
+        .filter_map(|uri| Some((uri.to_file_path().ok()?, files.get(uri)?)))
+        .map(|(path, document)| (path, document.text.as_str().into()))
+        .collect();
+    state.set_import_resolver(Box::new(Resolver {
+        disk: FileImportResolver {
+            library_paths: files.jpath().to_vec(),
+        },
+        open,
+    }));
+    state
+}
+
+/// Resolves imports like the jsonnet command line tool, but takes the
+/// documents open in the editor as they are edited, saved or not.
+struct Resolver {
+    disk: FileImportResolver,
+    open: HashMap<PathBuf, IStr>,
+}
+
+impl ImportResolver for Resolver {
+    fn resolve_file(&self, from: &PathBuf, path: &PathBuf) -> EvalResult<Rc<PathBuf>> {
+        let found = std::iter::once(from)
+            .chain(&self.disk.library_paths)
+            .map(|dir| dir.join(path))
+            .find(|candidate| self.open.contains_key(candidate) || candidate.is_file());
+        match found {
+            Some(resolved) => Ok(Rc::new(resolved)),
+            // Fails the way the command line tool does.
+            None => self.disk.resolve_file(from, path),
+        }
+    }
+
+    fn load_file_contents(&self, resolved: &PathBuf) -> EvalResult<IStr> {
+        match self.open.get(resolved) {
+            Some(text) => Ok(text.clone()),
+            None => self.disk.load_file_contents(resolved),
+        }
+    }
+
+    unsafe fn as_any(&self) -> &dyn Any {
+        panic!("the language server's import resolver is not meant for bindings")
+    }
+}
+
+/// Evaluates `code` as if it was written in the file at `path` and returns
+/// the manifested JSON.
+pub fn snippet(files: &Files, path: &Path, code: &str) -> Option<serde_json::Value> {
+    let state = state(files);
+    let manifested = state.run_in_state(|| {
+        let value = state.evaluate_snippet_raw(Rc::new(path.to_path_buf()), code.into())?;
+        state.manifest(value)
+    });
+    match manifested {
+        Ok(json) => serde_json::from_str(&json).ok(),
+        Err(err) => {
+            debug!("Failed to evaluate {}: {}", code, err.error());
+            None
+        }
+    }
+}
+
+/// The error the document at `uri` fails to evaluate with, if any. It is
+/// placed at the innermost frame of the stack trace that is in the document
+/// itself, and every frame becomes related information.
+pub fn diagnostics(files: &Files, uri: &Url) -> Vec<Diagnostic> {
+    let evaluation = match files.evaluation(uri) {
+        Some(evaluation) => evaluation,
+        None => return vec![],
+    };
+    let err = match evaluation.error() {
+        Some(err) => err,
+        None => return vec![],
+    };
+    let frames: Vec<DiagnosticRelatedInformation> = err
+        .trace()
+        .0
+        .iter()
+        .filter_map(|element| {
+            let location = element.location.as_ref()?;
+            let uri = Url::from_file_path(&*location.0).ok()?;
+            let document = files.get(&uri)?;
+            Some(DiagnosticRelatedInformation {
+                location: Location {
+                    uri,
+                    range: utils::offset_range_to_range(&document.text, location.1, location.2),
+                },
+                message: element.desc.clone(),
+            })
+        })
+        .collect();
+    let in_document = frames.iter().find(|frame| frame.location.uri == *uri);
+    let mut message = err.error().to_string();
+    let range = match in_document {
+        Some(frame) => frame.location.range,
+        None => {
+            let document = match files.get(uri) {
+                Some(document) => document,
+                None => return vec![],
+            };
+            let failed: Vec<&Url> = frames.iter().map(|frame| &frame.location.uri).collect();
+            let (range, context) = fallback_range(files, uri, &document, &failed);
+            message = format!("{}: {}", context, message);
+            range
+        }
+    };
+    vec![Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::Error),
+        message,
+        related_information: if frames.is_empty() {
+            None
+        } else {
+            Some(frames)
+        },
+        ..Diagnostic::default()
+    }]
+}
+
+/// Where to show an evaluation error that happened outside the document:
+/// on the import of the first failed file it imports, or else on its first
+/// line. Also returns what to prefix the message with.
+fn fallback_range(
+    files: &Files,
+    uri: &Url,
+    document: &Document,
+    failed: &[&Url],
+) -> (Range, String) {
+    let text = &document.text;
+    for (path, start, end) in links::imports(document) {
+        if let Some(imported) = files.resolve(uri, path) {
+            if failed.contains(&&imported) {
+                return (
+                    utils::offset_range_to_range(text, start, end),
+                    format!("Evaluating `{}` fails", path.display()),
+                );
+            }
+        }
+    }
+    let first_line = text.find('\n').unwrap_or(text.len());
+    let range = Range::new(
+        Position::new(0, 0),
+        utils::offset_to_position(text, first_line),
+    );
+    (range, "Evaluating this document fails".to_string())
+}
+
+/// The top-level evaluation of a document. Its state keeps the evaluated
+/// document, so expressions can be evaluated against it afterwards.
+pub struct Evaluation {
+    state: EvaluationState,
+    path: Rc<PathBuf>,
+    result: Result<Val, LocError>,
+    /// What manifesting the value failed with. Fields are only evaluated
+    /// then, so this is where most errors show.
+    manifest_error: Option<LocError>,
+}
+
+impl Evaluation {
+    pub fn new(files: &Files, path: PathBuf, document: &Document) -> Option<Evaluation> {
+        let ast = document.parsed.as_ref().ok()?;
+        let state = state(files);
+        let path = Rc::new(path);
+        let (result, manifest_error) = state.run_in_state(|| {
+            let result = state
+                .add_parsed_file(path.clone(), document.text.as_str().into(), ast.clone())
+                .and_then(|()| state.evaluate_loaded_file_raw(&path));
+            let manifest_error = match &result {
+                Ok(value) => state.manifest(value.clone()).err(),
+                Err(_) => None,
+            };
+            (result, manifest_error)
+        });
+        Some(Evaluation {
+            state,
+            path,
+            result,
+            manifest_error,
+        })
+    }
+
+    /// The error evaluating or manifesting the document failed with.
+    pub fn error(&self) -> Option<&LocError> {
+        self.result.as_ref().err().or(self.manifest_error.as_ref())
+    }
+
+    /// The path the evaluated document is imported by in [`Evaluation::value`].
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Evaluates `code`, written next to the document, and returns the
+    /// manifested JSON, cut off after [`MAX_VALUE_LINES`] lines.
+    pub fn value(&self, code: &str) -> Option<String> {
+        self.result.as_ref().ok()?;
+        // A name of its own, so the document itself stays loaded.
+        let source = Rc::new(self.path.with_file_name("<hover>"));
+        let manifested = self.state.run_in_state(|| {
+            let value = self.state.evaluate_snippet_raw(source, code.into())?;
+            self.state.manifest(value)
+        });
+        let json = match manifested {
+            Ok(json) => json,
+            Err(err) => {
+                debug!("Failed to evaluate {}: {}", code, err.error());
+                return None;
+            }
+        };
+        let mut lines: Vec<&str> = json.lines().take(MAX_VALUE_LINES + 1).collect();
+        if lines.len() > MAX_VALUE_LINES {
+            lines[MAX_VALUE_LINES] = "...";
+        }
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::{Position, Range, Url};
+
+    use crate::{document::Document, files::Files, testing::TempDir};
+
+    /// The evaluation error of `code`, written to `main.jsonnet` in `root`
+    /// and opened, with its range and the location of each frame.
+    fn error(root: &TempDir, code: &str) -> (String, Range, Vec<(Url, Range)>) {
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        fs::write(root.join("main.jsonnet"), code).unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let mut diagnostics = super::diagnostics(&files, &uri);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = diagnostics.remove(0);
+        let frames = diagnostic
+            .related_information
+            .unwrap_or_default()
+            .into_iter()
+            .map(|frame| (frame.location.uri, frame.location.range))
+            .collect();
+        (diagnostic.message, diagnostic.range, frames)
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Range {
+        Range::new(Position::new(line, start), Position::new(line, end))
+    }
+
+    #[test]
+    fn error_in_field() {
+        let root = TempDir::new("evaluate-field");
+        let main = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let (message, error_range, frames) = error(&root, "{\n  a: 1,\n  b: error 'broken',\n}");
+        assert!(message.contains("broken"));
+        assert_eq!(error_range, range(2, 5, 19));
+        assert_eq!(frames[0], (main, error_range));
+    }
+
+    #[test]
+    fn failed_object_assert() {
+        let root = TempDir::new("evaluate-assert");
+        let main = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let (message, error_range, frames) = error(
+            &root,
+            "{\n  assert self.a > 1 : 'a is too small',\n  a: 1,\n}",
+        );
+        assert!(message.contains("a is too small"));
+        assert_eq!(error_range.start.line, 1);
+        assert!(frames
+            .iter()
+            .all(|(uri, frame)| *uri == main && frame.start.line == 1));
+        assert!(!frames.is_empty());
+    }
+
+    #[test]
+    fn error_in_import() {
+        let root = TempDir::new("evaluate-import");
+        fs::write(
+            root.join("lib.libsonnet"),
+            "{\n  broken: error 'in lib',\n}",
+        )
+        .unwrap();
+        let main = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let lib = Url::from_file_path(root.join("lib.libsonnet")).unwrap();
+        let (message, error_range, frames) = error(
+            &root,
+            "local lib = import 'lib.libsonnet';\n{\n  value: lib.broken,\n}",
+        );
+        assert!(message.contains("in lib"));
+        // The error is shown where this document uses the import.
+        assert_eq!(error_range, range(2, 9, 19));
+        assert_eq!(frames[0], (lib, range(1, 10, 24)));
+        assert!(frames.contains(&(main, error_range)));
+    }
+
+    #[test]
+    fn unsaved_import() {
+        let root = TempDir::new("evaluate-unsaved");
+        fs::write(root.join("lib.libsonnet"), "{ a: 1 }").unwrap();
+        let lib = Url::from_file_path(root.join("lib.libsonnet")).unwrap();
+        let code = "local lib = import 'lib.libsonnet';\nlib.a";
+        fs::write(root.join("main.jsonnet"), code).unwrap();
+        let main = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+
+        let mut files = Files::new(vec![]);
+        files.insert(main.clone(), Document::new(&main, code.to_string()));
+        assert!(super::diagnostics(&files, &main).is_empty());
+        // The edited library is evaluated, not the one on disk.
+        let edited = "{ a: error 'unsaved' }";
+        files.insert(lib.clone(), Document::new(&lib, edited.to_string()));
+        let diagnostics = super::diagnostics(&files, &main);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("unsaved"));
+    }
+
+    #[test]
+    fn errors_outside_the_document() {
+        let root = TempDir::new("evaluate-outside");
+        fs::write(root.join("lib.libsonnet"), "{}").unwrap();
+        let lib = Url::from_file_path(root.join("lib.libsonnet")).unwrap();
+        let other = Url::from_file_path(root.join("other.libsonnet")).unwrap();
+        let main = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let code = "local lib = import 'lib.libsonnet';\nlib";
+        let document = Document::new(&main, code.to_string());
+        let files = Files::new(vec![]);
+
+        // On the import that led to the failure.
+        let (error_range, context) = super::fallback_range(&files, &main, &document, &[&lib]);
+        assert_eq!(error_range, range(0, 19, 34));
+        assert_eq!(context, "Evaluating `lib.libsonnet` fails");
+        // Or on the first line.
+        let (error_range, context) = super::fallback_range(&files, &main, &document, &[&other]);
+        assert_eq!(error_range, range(0, 0, 35));
+        assert_eq!(context, "Evaluating this document fails");
+    }
+}
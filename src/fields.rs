@@ -0,0 +1,434 @@
+//! Static resolution of objects: which object literals an expression is
+//! composed of and which member defines a field of it.
+//!
+//! This follows locals, imports, field accesses, `+` and object extension,
+//! function calls and `self`, `super` and `$`. Anything depending on runtime
+//! values, like parameters or computed field names, is not resolved.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use jrsonnet_parser::{
+    BinaryOpType, Expr, FieldMember, FieldName, LiteralType, LocExpr, Member, ObjBody, ParamsDesc,
+    Visibility,
+};
+use lsp_types::{Location, Url};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+    files::Files,
+    scope::{self, Analysis},
+    utils,
+};
+
+/// Guards against cycles like `local a = a + {}`.
+const MAX_DEPTH: usize = 64;
+
+/// An expression together with the document it is written in.
+#[derive(Clone)]
+pub struct Expression {
+    pub uri: Url,
+    pub document: Rc<Document>,
+    pub expr: LocExpr,
+}
+
+impl Expression {
+    pub fn with(&self, expr: LocExpr) -> Expression {
+        Expression {
+            uri: self.uri.clone(),
+            document: self.document.clone(),
+            expr,
+        }
+    }
+}
+
+/// An object as the object literals it is composed of. Later layers
+/// override earlier ones.
+pub struct Object {
+    pub layers: Vec<Expression>,
+}
+
+impl Object {
+    /// The fields of all layers, in order.
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![];
+        for layer in &self.layers {
+            let (members, start) = match members(&layer.expr) {
+                Some(members) => members,
+                None => continue,
+            };
+            let mut member_start = start;
+            for (index, member) in members.iter().enumerate() {
+                if let Member::Field(FieldMember {
+                    name: FieldName::Fixed(_),
+                    ..
+                }) = member
+                {
+                    if let Some((start, end)) = name_span(&layer.document, member_start) {
+                        fields.push(Field {
+                            layer: layer.clone(),
+                            index,
+                            start,
+                            end,
+                        });
+                    }
+                }
+                member_start = scope::member_end(member);
+            }
+        }
+        fields
+    }
+
+    /// The fields called `name`, in order. The last one is the one that
+    /// defines the value, unless it uses `+:`.
+    pub fn lookup(&self, name: &str) -> Vec<Field> {
+        self.fields()
+            .into_iter()
+            .filter(|field| field.name() == name)
+            .collect()
+    }
+
+    /// Whether the field `name` is hidden: `::` hides it and `:::` shows it
+    /// again, a plain `:` keeps what the layers before decided.
+    pub fn hidden(&self, name: &str) -> bool {
+        self.lookup(name)
+            .iter()
+            .fold(false, |hidden, field| match field.member().visibility {
+                Visibility::Hidden => true,
+                Visibility::Unhide => false,
+                Visibility::Normal => hidden,
+            })
+    }
+
+    /// The fields that make up the value of `name`: the last one and the
+    /// ones it extends with `+:`.
+    pub fn defining(&self, name: &str) -> Vec<Field> {
+        let mut fields = self.lookup(name);
+        let first = fields
+            .iter()
+            .rposition(|field| !field.member().plus)
+            .unwrap_or(0);
+        fields.drain(..first);
+        fields
+    }
+}
+
+pub struct Field {
+    /// The object literal the field is a member of.
+    pub layer: Expression,
+    index: usize,
+    /// Byte range of the field name.
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Field {
+    pub fn member(&self) -> &FieldMember {
+        match members(&self.layer.expr).map(|(members, _)| &members[self.index]) {
+            Some(Member::Field(field)) => field,
+            _ => unreachable!("fields are only created for field members"),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.member().name {
+            FieldName::Fixed(name) => name,
+            FieldName::Dyn(_) => unreachable!("fields are only created for fixed names"),
+        }
+    }
+
+    pub fn value(&self) -> Expression {
+        self.layer.with(self.member().value.clone())
+    }
+
+    pub fn location(&self) -> Location {
+        Location {
+            uri: self.layer.uri.clone(),
+            range: utils::offset_range_to_range(&self.layer.document.text, self.start, self.end),
+        }
+    }
+
+    pub fn is(&self, other: &Field) -> bool {
+        self.layer.uri == other.layer.uri && self.start == other.start
+    }
+}
+
+/// The field access `target.name` or `target['name']` if `path` ends at
+/// its name.
+pub fn access<'a>(path: &[&'a LocExpr]) -> Option<(&'a LocExpr, &'a str)> {
+    match path {
+        [.., parent, last] => match (&*parent.0, &*last.0) {
+            (Expr::Index(target, index), Expr::Str(name)) if Rc::ptr_eq(&index.0, &last.0) => {
+                Some((target, name))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The field whose name is at `offset` in the document.
+pub fn field_at(document: &Expression, offset: usize) -> Option<Field> {
+    let path = cst::path_at(&document.expr, offset);
+    let object = path.iter().rev().find(|expr| members(expr).is_some())?;
+    Object {
+        layers: vec![document.with((*object).clone())],
+    }
+    .fields()
+    .into_iter()
+    .find(|field| field.start <= offset && offset <= field.end)
+}
+
+/// The members of an object literal and the offset they start after.
+fn members(expr: &LocExpr) -> Option<(&[Member], usize)> {
+    match &*expr.0 {
+        Expr::Obj(ObjBody::MemberList(members)) => Some((members, scope::start(expr))),
+        Expr::ObjExtend(base, ObjBody::MemberList(members)) => Some((members, scope::end(base))),
+        _ => None,
+    }
+}
+
+/// The field name is the first identifier or string after the previous
+/// member.
+fn name_span(document: &Document, offset: usize) -> Option<(usize, usize)> {
+    let tokens = &document.cst.tokens;
+    let first = tokens.partition_point(|t| t.start < offset);
+    tokens[first..]
+        .iter()
+        .find(|t| matches!(t.kind, TokenKind::Ident | TokenKind::String))
+        .map(|t| (t.start, t.end))
+}
+
+pub struct Resolver<'a> {
+    files: &'a Files,
+    analyses: RefCell<HashMap<Url, Rc<Analysis>>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(files: &'a Files) -> Resolver<'a> {
+        Resolver {
+            files,
+            analyses: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn analysis(&self, expression: &Expression) -> Rc<Analysis> {
+        self.analyses
+            .borrow_mut()
+            .entry(expression.uri.clone())
+            .or_insert_with(|| Rc::new(scope::analyze(&expression.document)))
+            .clone()
+    }
+
+    /// The value a variable is bound to, or the body and parameters if it
+    /// is bound to a function with `local f(x) = ...`.
+    fn binding(&self, var: &Expression) -> Option<(Expression, Option<ParamsDesc>)> {
+        let analysis = self.analysis(var);
+        let definition = &analysis.definitions[analysis.definition_at(scope::start(&var.expr))?];
+        let value = definition.value.clone()?;
+        Some((var.with(value), definition.params.clone()))
+    }
+
+    pub fn object(&self, expression: &Expression) -> Option<Object> {
+        self.object_at(expression, 0)
+    }
+
+    fn object_at(&self, expression: &Expression, depth: usize) -> Option<Object> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let depth = depth + 1;
+        let inner = |expr: &LocExpr| self.object_at(&expression.with(expr.clone()), depth);
+        match &*expression.expr.0 {
+            Expr::Obj(_) => Some(Object {
+                layers: vec![expression.clone()],
+            }),
+            Expr::ObjExtend(base, _) => {
+                let mut layers = inner(base).map_or_else(Vec::new, |object| object.layers);
+                layers.push(expression.clone());
+                Some(Object { layers })
+            }
+            Expr::BinaryOp(left, BinaryOpType::Add, right) => match (inner(left), inner(right)) {
+                (None, None) => None,
+                (left, right) => {
+                    let mut layers = left.map_or_else(Vec::new, |object| object.layers);
+                    layers.extend(right.into_iter().flat_map(|object| object.layers));
+                    Some(Object { layers })
+                }
+            },
+            Expr::Parened(inner_expr)
+            | Expr::LocalExpr(_, inner_expr)
+            | Expr::AssertExpr(_, inner_expr) => inner(inner_expr),
+            Expr::IfElse {
+                cond_then,
+                cond_else,
+                ..
+            } => inner(cond_then).or_else(|| cond_else.as_ref().and_then(inner)),
+            Expr::Var(_) => match self.binding(expression)? {
+                (_, Some(_)) => None,
+                (value, None) => self.object_at(&value, depth),
+            },
+            Expr::Import(path) => {
+                let uri = self.files.resolve(&expression.uri, path)?;
+                let document = self.files.get(&uri)?;
+                let expr = document.ast.clone();
+                self.object_at(
+                    &Expression {
+                        uri,
+                        document,
+                        expr,
+                    },
+                    depth,
+                )
+            }
+            Expr::Literal(
+                literal @ (LiteralType::This | LiteralType::Super | LiteralType::Dollar),
+            ) => self.this(expression, *literal, depth),
+            Expr::Index(target, index) => {
+                let name = match &*index.0 {
+                    Expr::Str(name) => name,
+                    _ => return None,
+                };
+                let fields = inner(target)?.defining(name);
+                let layers: Vec<_> = fields
+                    .iter()
+                    .filter_map(|field| self.object_at(&field.value(), depth))
+                    .flat_map(|object| object.layers)
+                    .collect();
+                if layers.is_empty() {
+                    None
+                } else {
+                    Some(Object { layers })
+                }
+            }
+            Expr::Apply(function, _, _) => {
+                let (_, body) = self.function_at(&expression.with(function.clone()), depth)?;
+                self.object_at(&body, depth)
+            }
+            _ => None,
+        }
+    }
+
+    /// The type of the value an expression evaluates to, named like
+    /// `std.type` does, if it can be told without evaluating it.
+    pub fn kind(&self, expression: &Expression) -> Option<&'static str> {
+        self.kind_at(expression, 0)
+    }
+
+    /// The type of the value of a field, see [`Resolver::kind`].
+    pub fn field_kind(&self, field: &Field) -> Option<&'static str> {
+        if field.member().params.is_some() {
+            Some("function")
+        } else {
+            self.kind(&field.value())
+        }
+    }
+
+    fn kind_at(&self, expression: &Expression, depth: usize) -> Option<&'static str> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let depth = depth + 1;
+        let inner = |expr: &LocExpr| self.kind_at(&expression.with(expr.clone()), depth);
+        match &*expression.expr.0 {
+            Expr::Str(_) | Expr::ImportStr(_) => Some("string"),
+            Expr::Num(_) => Some("number"),
+            Expr::Literal(LiteralType::True | LiteralType::False) => Some("boolean"),
+            Expr::Literal(LiteralType::Null) => Some("null"),
+            Expr::Arr(_) | Expr::ArrComp(..) => Some("array"),
+            Expr::Function(..) => Some("function"),
+            Expr::Parened(inner_expr)
+            | Expr::LocalExpr(_, inner_expr)
+            | Expr::AssertExpr(_, inner_expr) => inner(inner_expr),
+            Expr::IfElse {
+                cond_then,
+                cond_else,
+                ..
+            } => inner(cond_then).or_else(|| cond_else.as_ref().and_then(inner)),
+            Expr::Var(_) => match self.binding(expression)? {
+                (_, Some(_)) => Some("function"),
+                (value, None) => self.kind_at(&value, depth),
+            },
+            _ => self.object_at(expression, depth).map(|_| "object"),
+        }
+    }
+
+    /// The parameters and the body of the function an expression
+    /// evaluates to.
+    pub fn function(&self, expression: &Expression) -> Option<(ParamsDesc, Expression)> {
+        self.function_at(expression, 0)
+    }
+
+    fn function_at(
+        &self,
+        expression: &Expression,
+        depth: usize,
+    ) -> Option<(ParamsDesc, Expression)> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let depth = depth + 1;
+        match &*expression.expr.0 {
+            Expr::Function(params, body) => Some((params.clone(), expression.with(body.clone()))),
+            Expr::Parened(inner) => self.function_at(&expression.with(inner.clone()), depth),
+            Expr::Var(_) => match self.binding(expression)? {
+                (body, Some(params)) => Some((params, body)),
+                (value, None) => self.function_at(&value, depth),
+            },
+            Expr::Index(target, index) => {
+                let name = match &*index.0 {
+                    Expr::Str(name) => name,
+                    _ => return None,
+                };
+                let object = self.object_at(&expression.with(target.clone()), depth)?;
+                let field = object.lookup(name).pop()?;
+                match &field.member().params {
+                    Some(params) => Some((params.clone(), field.value())),
+                    None => self.function_at(&field.value(), depth),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The object `self`, `super` or `$` refers to. That is the object
+    /// literal containing it, together with everything it is combined with
+    /// using `+` or object extension.
+    fn this(&self, expression: &Expression, literal: LiteralType, depth: usize) -> Option<Object> {
+        let offset = scope::start(&expression.expr);
+        let path = cst::path_at(&expression.document.ast, offset);
+        let in_body = |expr: &&LocExpr| match &*expr.0 {
+            Expr::Obj(_) => true,
+            Expr::ObjExtend(base, _) => offset >= scope::end(base),
+            _ => false,
+        };
+        let mut i = match literal {
+            LiteralType::Dollar => path.iter().position(in_body)?,
+            _ => path.iter().rposition(in_body)?,
+        };
+        let literal_object = path[i];
+        while i > 0 && extends(path[i - 1], path[i]) {
+            i -= 1;
+        }
+        let object = self.object_at(&expression.with(path[i].clone()), depth)?;
+        if let LiteralType::Super = literal {
+            let current = object
+                .layers
+                .iter()
+                .position(|layer| Rc::ptr_eq(&layer.expr.0, &literal_object.0))?;
+            return Some(Object {
+                layers: object.layers[..current].to_vec(),
+            });
+        }
+        Some(object)
+    }
+}
+
+/// Whether `parent` combines the object `child` with others.
+fn extends(parent: &LocExpr, child: &LocExpr) -> bool {
+    match &*parent.0 {
+        Expr::Parened(_) | Expr::BinaryOp(_, BinaryOpType::Add, _) => true,
+        Expr::ObjExtend(base, _) => Rc::ptr_eq(&base.0, &child.0),
+        _ => false,
+    }
+}
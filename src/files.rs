@@ -0,0 +1,187 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use log::warn;
+use lsp_types::Url;
+
+use crate::{document::Document, evaluate::Evaluation};
+
+/// All documents the server knows about: the ones opened by the editor and
+/// the ones that were read from disk because they are imported.
+pub struct Files {
+    open: HashMap<Url, Rc<Document>>,
+    loaded: RefCell<HashMap<Url, Rc<Document>>>,
+    evaluations: RefCell<HashMap<Url, Option<Rc<Evaluation>>>>,
+    /// Library search paths, tried in order after the directory of the
+    /// importing file.
+    jpath: Vec<PathBuf>,
+}
+
+impl Files {
+    pub fn new(jpath: Vec<PathBuf>) -> Files {
+        Files {
+            open: HashMap::new(),
+            loaded: RefCell::new(HashMap::new()),
+            evaluations: RefCell::new(HashMap::new()),
+            jpath,
+        }
+    }
+
+    /// Stores the editor's version of a document, which takes precedence
+    /// over the file on disk.
+    pub fn insert(&mut self, uri: Url, document: Document) {
+        self.loaded.get_mut().remove(&uri);
+        // Other documents may import this one.
+        self.evaluations.get_mut().clear();
+        self.open.insert(uri, Rc::new(document));
+    }
+
+    /// Drops the editor's version of a document after it was closed, so the
+    /// file on disk is read again.
+    pub fn close(&mut self, uri: &Url) {
+        self.open.remove(uri);
+        self.evaluations.get_mut().clear();
+    }
+
+    /// Forgets what was read from disk for `uri` after the file changed
+    /// there, along with all evaluations, which may have imported it.
+    pub fn invalidate(&mut self, uri: &Url) {
+        self.loaded.get_mut().remove(uri);
+        self.evaluations.get_mut().clear();
+    }
+
+    /// The documents opened by the editor.
+    pub fn opened(&self) -> impl Iterator<Item = &Url> {
+        self.open.keys()
+    }
+
+    pub fn jpath(&self) -> &[PathBuf] {
+        &self.jpath
+    }
+
+    /// Returns the document for `uri`, reading and parsing it from disk if
+    /// the editor has not opened it.
+    pub fn get(&self, uri: &Url) -> Option<Rc<Document>> {
+        if let Some(document) = self.open.get(uri) {
+            return Some(document.clone());
+        }
+        if let Some(document) = self.loaded.borrow().get(uri) {
+            return Some(document.clone());
+        }
+        let path = uri.to_file_path().ok()?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("Failed to read {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        let document = Rc::new(Document::new(uri, text));
+        self.loaded
+            .borrow_mut()
+            .insert(uri.clone(), document.clone());
+        Some(document)
+    }
+
+    /// The evaluation of the document at `uri`, which is done once per
+    /// version of the workspace. `None` if it does not parse.
+    pub fn evaluation(&self, uri: &Url) -> Option<Rc<Evaluation>> {
+        if let Some(evaluation) = self.evaluations.borrow().get(uri) {
+            return evaluation.clone();
+        }
+        let document = self.get(uri)?;
+        let path = uri.to_file_path().ok()?;
+        let evaluation = Evaluation::new(self, path, &document).map(Rc::new);
+        self.evaluations
+            .borrow_mut()
+            .insert(uri.clone(), evaluation.clone());
+        evaluation
+    }
+
+    /// Resolves the path of an `import` or `importstr` in the document at
+    /// `from`, like the jsonnet command line tool does.
+    pub fn resolve(&self, from: &Url, path: &Path) -> Option<Url> {
+        if path.is_absolute() {
+            return existing(path.to_path_buf());
+        }
+        self.search_dirs(from)
+            .into_iter()
+            .find_map(|dir| existing(dir.join(path)))
+    }
+
+    /// The directories relative imports in the document at `from` are
+    /// looked up in: its own directory and then the library paths.
+    pub fn search_dirs(&self, from: &Url) -> Vec<PathBuf> {
+        let dir = from
+            .to_file_path()
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf));
+        dir.into_iter().chain(self.jpath.iter().cloned()).collect()
+    }
+}
+
+fn existing(path: PathBuf) -> Option<Url> {
+    if path.is_file() {
+        Url::from_file_path(path).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use lsp_types::Url;
+
+    use super::Files;
+    use crate::{document::Document, testing::TempDir};
+
+    #[test]
+    fn resolve_and_load() {
+        let root = TempDir::new("files");
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("vendor/k")).unwrap();
+        fs::write(root.join("app/local.libsonnet"), "{ a: 1 }").unwrap();
+        fs::write(root.join("vendor/k/k.libsonnet"), "{ b: 2 }").unwrap();
+
+        let mut files = Files::new(vec![root.join("vendor")]);
+        let main = Url::from_file_path(root.join("app/main.jsonnet")).unwrap();
+
+        let local = files.resolve(&main, Path::new("local.libsonnet")).unwrap();
+        assert_eq!(
+            local.to_file_path().unwrap(),
+            root.join("app/local.libsonnet")
+        );
+        let vendored = files.resolve(&main, Path::new("k/k.libsonnet")).unwrap();
+        assert_eq!(
+            vendored.to_file_path().unwrap(),
+            root.join("vendor/k/k.libsonnet")
+        );
+        assert_eq!(files.resolve(&main, Path::new("missing.libsonnet")), None);
+
+        assert_eq!(files.get(&vendored).unwrap().text, "{ b: 2 }");
+        assert!(files.get(&main).is_none());
+
+        // Changes on disk show once the file is invalidated.
+        fs::write(root.join("vendor/k/k.libsonnet"), "{ b: 3 }").unwrap();
+        assert_eq!(files.get(&vendored).unwrap().text, "{ b: 2 }");
+        files.invalidate(&vendored);
+        assert_eq!(files.get(&vendored).unwrap().text, "{ b: 3 }");
+
+        // Closed documents are read from disk again.
+        files.insert(
+            vendored.clone(),
+            Document::new(&vendored, "{ b: 4 }".to_string()),
+        );
+        assert_eq!(files.get(&vendored).unwrap().text, "{ b: 4 }");
+        files.close(&vendored);
+        assert_eq!(files.get(&vendored).unwrap().text, "{ b: 3 }");
+        assert_eq!(files.opened().count(), 0);
+    }
+}
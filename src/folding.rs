@@ -0,0 +1,174 @@
+//! Folding ranges for multi-line objects, arrays, functions, text blocks
+//! and comments.
+
+use jrsonnet_parser::Expr;
+use lsp_types::{FoldingRange, FoldingRangeKind, Url};
+
+use crate::{
+    cst::{self, TokenKind},
+    files::Files,
+    scope, utils,
+};
+
+pub fn ranges(files: &Files, uri: &Url) -> Vec<FoldingRange> {
+    let document = match files.get(uri) {
+        Some(document) => document,
+        None => return vec![],
+    };
+    let text = &document.text;
+    let line = |offset: usize| utils::offset_to_position(text, offset).line;
+    let mut ranges = vec![];
+    let mut fold = |start: usize, end_line: u32, kind: Option<FoldingRangeKind>| {
+        let start_line = line(start);
+        if end_line > start_line {
+            ranges.push(FoldingRange {
+                start_line,
+                start_character: None,
+                end_line,
+                end_character: None,
+                kind,
+            });
+        }
+    };
+
+    for expr in cst::descendants(&document.ast) {
+        match &*expr.0 {
+            Expr::Obj(_)
+            | Expr::ObjExtend(..)
+            | Expr::Arr(_)
+            | Expr::ArrComp(..)
+            | Expr::Function(..) => {
+                // The line with the closing bracket stays visible.
+                let end = scope::end(expr);
+                let closed = matches!(text[..end].chars().last(), Some('}' | ']'));
+                fold(
+                    scope::start(expr),
+                    line(end).saturating_sub(closed as u32),
+                    None,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    let tokens = &document.cst.tokens;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        match token.kind {
+            TokenKind::BlockComment => fold(
+                token.start,
+                line(token.end),
+                Some(FoldingRangeKind::Comment),
+            ),
+            TokenKind::String if token.text(text).starts_with("|||") => {
+                fold(token.start, line(token.end), None)
+            }
+            // Comments after code on the same line start no run.
+            TokenKind::LineComment if !first_on_line(text, token.start) => {}
+            TokenKind::LineComment => {
+                // A run of `//` comments on consecutive lines.
+                let mut last = i;
+                while let [whitespace, next, ..] = &tokens[last + 1..] {
+                    let single_newline = whitespace.kind == TokenKind::Whitespace
+                        && whitespace.text(text).matches('\n').count() == 1;
+                    if !single_newline || next.kind != TokenKind::LineComment {
+                        break;
+                    }
+                    last += 2;
+                }
+                fold(
+                    token.start,
+                    line(tokens[last].end),
+                    Some(FoldingRangeKind::Comment),
+                );
+                i = last;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    ranges.sort_by_key(|range| (range.start_line, range.end_line));
+    ranges.dedup_by_key(|range| (range.start_line, range.end_line));
+    ranges
+}
+
+/// Whether only whitespace comes before `offset` on its line.
+fn first_on_line(text: &str, offset: usize) -> bool {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    text[line_start..offset].trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{FoldingRangeKind, Url};
+
+    use crate::{document::Document, files::Files};
+
+    #[test]
+    fn folds() {
+        let code = r#"// Header
+// comment
+local x = 1;  // trailing
+/*
+ * Block
+ */
+{
+  list: [
+    1,
+    2,
+  ],
+  squares: [
+    i * i
+    for i in std.range(0, 10)
+  ],
+  text: |||
+    line
+    line
+  |||,
+  f(a):: function(b)
+    a + b,
+  inline: { a: 1 },
+}
+"#;
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let ranges: Vec<_> = super::ranges(&files, &uri)
+            .into_iter()
+            .map(|range| {
+                let comment = range.kind == Some(FoldingRangeKind::Comment);
+                (range.start_line, range.end_line, comment)
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                (0, 1, true),
+                (3, 5, true),
+                (6, 21, false),
+                (7, 9, false),
+                (11, 13, false),
+                (15, 18, false),
+                (19, 20, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_comments() {
+        let code = "{\n  a: 1,  // trailing\n  // own\n  // lines\n  b: 2,\n}\n";
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let ranges: Vec<_> = super::ranges(&files, &uri)
+            .into_iter()
+            .map(|range| (range.start_line, range.end_line, range.kind))
+            .collect();
+        assert_eq!(
+            ranges,
+            [(0, 4, None), (2, 3, Some(FoldingRangeKind::Comment))]
+        );
+    }
+}
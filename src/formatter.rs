@@ -0,0 +1,926 @@
+use jrsonnet_parser::{
+    ArgsDesc, AssertStmt, BinaryOpType, BindSpec, CompSpec, Expr, FieldMember, FieldName,
+    LiteralType, LocExpr, Member, ObjBody, ParamsDesc, UnaryOpType, Visibility,
+};
+
+use log::warn;
+
+use crate::{
+    cst::{self, TokenKind},
+    utils,
+};
+
+const INDENT: &str = "  ";
+
+/// Formats a parsed document in the canonical `jsonnetfmt` style.
+///
+/// The AST does not know whether an object or array was written on a single
+/// line, so the original source is consulted through the expression
+/// locations to keep short literals inline. Comments, blank lines between
+/// members and the original spelling of string literals are taken from the
+/// concrete syntax tree.
+///
+/// Returns `None` if a comment sits somewhere the printer cannot place it, so
+/// formatting never drops comments.
+pub fn format(code: &str, tree: &cst::Tree, ast: &LocExpr) -> Option<String> {
+    let mut printer = Printer {
+        code,
+        tree,
+        out: String::new(),
+        indent: 0,
+        comments: 0,
+    };
+    if let Some(pos) = printer.comments(Some(0), start(ast)) {
+        printer.newline();
+        if printer.blank_line(Some(pos), start(ast)) {
+            printer.newline();
+        }
+    }
+    printer.expr(ast);
+    printer.comments(end(ast), Some(code.len()));
+    printer.out.push('\n');
+    if printer.comments != tree.comment_count() {
+        return None;
+    }
+    Some(printer.out)
+}
+
+/// Returns the edits that replace the whole document with its formatted
+/// version, or nothing if it is already formatted.
+pub fn edits(code: &str, tree: &cst::Tree, ast: &LocExpr) -> Vec<lsp_types::TextEdit> {
+    let formatted = match format(code, tree, ast) {
+        Some(formatted) => formatted,
+        None => {
+            warn!("Not formatting, some comments could not be placed");
+            return vec![];
+        }
+    };
+    if formatted == code {
+        return vec![];
+    }
+    vec![lsp_types::TextEdit {
+        range: utils::offset_range_to_range(code, 0, code.len()),
+        new_text: formatted,
+    }]
+}
+
+pub fn binary_op(op: BinaryOpType) -> &'static str {
+    use BinaryOpType::*;
+    match op {
+        Mul => "*",
+        Div => "/",
+        Mod => "%",
+        Add => "+",
+        Sub => "-",
+        Lhs => "<<",
+        Rhs => ">>",
+        Lt => "<",
+        Gt => ">",
+        Lte => "<=",
+        Gte => ">=",
+        BitAnd => "&",
+        BitOr => "|",
+        BitXor => "^",
+        Eq => "==",
+        Neq => "!=",
+        And => "&&",
+        Or => "||",
+    }
+}
+
+fn unary_op(op: UnaryOpType) -> &'static str {
+    match op {
+        UnaryOpType::Plus => "+",
+        UnaryOpType::Minus => "-",
+        UnaryOpType::BitNot => "~",
+        UnaryOpType::Not => "!",
+    }
+}
+
+pub fn visibility(plus: bool, visibility: Visibility) -> &'static str {
+    match (plus, visibility) {
+        (false, Visibility::Normal) => ":",
+        (false, Visibility::Hidden) => "::",
+        (false, Visibility::Unhide) => ":::",
+        (true, Visibility::Normal) => "+:",
+        (true, Visibility::Hidden) => "+::",
+        (true, Visibility::Unhide) => "+:::",
+    }
+}
+
+/// Renders `value` as a single quoted Jsonnet string literal.
+pub fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('\'');
+    for c in value.chars() {
+        match c {
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+fn start(expr: &LocExpr) -> Option<usize> {
+    expr.1.as_ref().map(|l| l.1)
+}
+
+fn end(expr: &LocExpr) -> Option<usize> {
+    expr.1.as_ref().map(|l| l.2)
+}
+
+fn member_end(member: &Member) -> Option<usize> {
+    match member {
+        Member::Field(field) => end(&field.value),
+        Member::BindStmt(bind) => end(&bind.value),
+        Member::AssertStmt(AssertStmt(cond, message)) => end(message.as_ref().unwrap_or(cond)),
+    }
+}
+
+fn number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
+
+struct Printer<'a> {
+    code: &'a str,
+    tree: &'a cst::Tree,
+    out: String,
+    indent: usize,
+    /// Number of comments written so far.
+    comments: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Returns whether the source between the two offsets spans multiple
+    /// lines. Without location data everything is treated as multi-line.
+    fn broken(&self, start: Option<usize>, end: Option<usize>) -> bool {
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end && end <= self.code.len() => {
+                self.code[start..end].contains('\n')
+            }
+            _ => true,
+        }
+    }
+
+    /// Like [`Printer::broken`], but comments within the range force a
+    /// multi-line layout too, as they can only be placed between lines.
+    fn multiline(&self, start: Option<usize>, end: Option<usize>) -> bool {
+        self.broken(start, end) || self.has_comments(start, end)
+    }
+
+    /// Returns whether the author started a new line between the offsets.
+    /// Unlike [`Printer::broken`], nothing is known without location data.
+    fn breaks(&self, start: Option<usize>, end: Option<usize>) -> bool {
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => self.code[start..end].contains('\n'),
+            _ => false,
+        }
+    }
+
+    fn has_comments(&self, start: Option<usize>, end: Option<usize>) -> bool {
+        match (start, end) {
+            (Some(start), Some(end)) => self.tree.comments(start, end).next().is_some(),
+            _ => false,
+        }
+    }
+
+    fn blank_line(&self, start: Option<usize>, end: Option<usize>) -> bool {
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => {
+                self.code[start..end].matches('\n').count() > 1
+            }
+            _ => false,
+        }
+    }
+
+    /// Start of the next list item after `pos`, skipping separators.
+    fn item_start(&self, pos: Option<usize>) -> Option<usize> {
+        let pos = pos?;
+        let first = self.tree.tokens.partition_point(|t| t.start < pos);
+        self.tree.tokens[first..]
+            .iter()
+            .find(|t| !t.kind.is_trivia() && t.text(self.code) != ",")
+            .map(|t| t.start)
+    }
+
+    /// The first token after `pos` that is not whitespace or a comment.
+    fn next_token(&self, pos: Option<usize>) -> Option<cst::Token> {
+        let pos = pos?;
+        let first = self.tree.tokens.partition_point(|t| t.start < pos);
+        self.tree.tokens[first..]
+            .iter()
+            .find(|t| !t.kind.is_trivia())
+            .copied()
+    }
+
+    /// The end of the token after `pos` if it is `symbol`.
+    fn after_symbol(&self, pos: Option<usize>, symbol: &str) -> Option<usize> {
+        self.next_token(pos)
+            .filter(|token| token.text(self.code) == symbol)
+            .map(|token| token.end)
+    }
+
+    /// Writes the comments between the two offsets, each on its own line
+    /// unless it shared a line with the preceding code. Returns the end of
+    /// the last comment written.
+    fn comments(&mut self, start: Option<usize>, end: Option<usize>) -> Option<usize> {
+        let (start, end) = (start?, end?);
+        let tree = self.tree;
+        let mut pos = start;
+        let mut written = None;
+        for token in tree.comments(start, end) {
+            if !self.out.is_empty() && self.code[pos..token.start].contains('\n') {
+                let opened = self.out.ends_with(['{', '[', '(']);
+                if !opened && self.blank_line(Some(pos), Some(token.start)) {
+                    self.out.push('\n');
+                }
+                self.newline();
+            } else if !self.out.is_empty() {
+                self.push(" ");
+            }
+            self.push(token.text(self.code).trim_end());
+            self.comments += 1;
+            pos = token.end;
+            written = Some(pos);
+        }
+        written
+    }
+
+    /// Writes what goes between two parts of an expression: the comments
+    /// there, then a line break if the author put one there, or else
+    /// `space`. The first break indents the rest of the expression by one
+    /// level, which `indented` keeps track of.
+    fn gap(&mut self, from: Option<usize>, to: Option<usize>, space: &str, indented: &mut bool) {
+        let broken = self.breaks(from, to);
+        if broken && !*indented {
+            self.indent += 1;
+            *indented = true;
+        }
+        self.comments(from, to);
+        if broken {
+            self.newline();
+        } else {
+            self.push(space);
+        }
+    }
+
+    /// Like [`Printer::gap`] before the closing bracket of an expression,
+    /// which goes back to the indentation the expression started with.
+    fn close(&mut self, from: Option<usize>, to: Option<usize>, space: &str, indented: bool) {
+        let broken = self.breaks(from, to);
+        self.comments(from, to);
+        if indented {
+            self.indent -= 1;
+        }
+        if broken {
+            self.newline();
+        } else {
+            self.push(space);
+        }
+    }
+
+    /// Writes one item of a multi-line list: the comments leading up to it,
+    /// a blank line if there was one, and the item itself.
+    fn item(&mut self, pos: Option<usize>, first: bool, f: impl FnOnce(&mut Self)) {
+        let item_start = self.item_start(pos);
+        let after_comments = self.comments(pos, item_start).or(pos);
+        if !first && self.blank_line(after_comments, item_start) {
+            self.out.push('\n');
+        }
+        self.newline();
+        f(self);
+        self.push(",");
+    }
+
+    /// Writes a string literal the way it was spelled, unless it was double
+    /// quoted and can be written with single quotes.
+    fn string(&mut self, expr: &LocExpr, value: &str) {
+        let token = self.tree.node_for(expr).and_then(|node| {
+            if node.tokens.len() == 1 {
+                Some(self.tree.tokens[node.tokens.start])
+            } else {
+                None
+            }
+        });
+        match token {
+            Some(token) if token.kind == TokenKind::String => {
+                let text = token.text(self.code);
+                if text.starts_with('"') && !value.contains('\'') {
+                    self.push(&quote(value));
+                } else {
+                    self.push(text);
+                }
+            }
+            _ => self.push(&quote(value)),
+        }
+    }
+
+    /// Writes a number literal the way it was spelled, `1e3` stays `1e3`.
+    fn number(&mut self, expr: &LocExpr, value: f64) {
+        let token = self.tree.node_for(expr).and_then(|node| {
+            if node.tokens.len() == 1 {
+                Some(self.tree.tokens[node.tokens.start])
+            } else {
+                None
+            }
+        });
+        match token {
+            Some(token) if token.kind == TokenKind::Number => self.push(token.text(self.code)),
+            _ => self.push(&number(value)),
+        }
+    }
+
+    fn expr(&mut self, expr: &LocExpr) {
+        match &*expr.0 {
+            Expr::Literal(literal) => self.push(match literal {
+                LiteralType::This => "self",
+                LiteralType::Super => "super",
+                LiteralType::Dollar => "$",
+                LiteralType::Null => "null",
+                LiteralType::True => "true",
+                LiteralType::False => "false",
+            }),
+            Expr::Str(value) => self.string(expr, value),
+            Expr::Num(value) => self.number(expr, *value),
+            Expr::Var(name) => self.push(name),
+            Expr::Arr(items) => {
+                if items.is_empty() {
+                    self.push("[]");
+                } else if self.multiline(start(expr), end(expr)) {
+                    self.push("[");
+                    self.indent += 1;
+                    let mut pos = start(expr).map(|pos| pos + 1);
+                    for (i, item) in items.iter().enumerate() {
+                        self.item(pos, i == 0, |p| p.expr(item));
+                        pos = end(item);
+                    }
+                    self.comments(pos, end(expr));
+                    self.indent -= 1;
+                    self.newline();
+                    self.push("]");
+                } else {
+                    self.push("[");
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            self.push(", ");
+                        }
+                        self.expr(item);
+                    }
+                    self.push("]");
+                }
+            }
+            Expr::ArrComp(value, specs) => {
+                let mut indented = false;
+                self.push("[");
+                self.gap(
+                    start(expr).map(|pos| pos + 1),
+                    start(value),
+                    "",
+                    &mut indented,
+                );
+                self.expr(value);
+                let specs_end = self.comp_specs(specs, end(value), &mut indented);
+                self.close(specs_end, end(expr).map(|pos| pos - 1), "", indented);
+                self.push("]");
+            }
+            Expr::Obj(body) => self.obj_body(body, start(expr), end(expr)),
+            Expr::ObjExtend(base, body) => {
+                self.expr(base);
+                self.push(" ");
+                let start = self.item_start(end(base));
+                self.obj_body(body, start, end(expr));
+            }
+            Expr::Parened(inner) => {
+                self.push("(");
+                self.expr(inner);
+                self.push(")");
+            }
+            Expr::UnaryOp(op, value) => {
+                self.push(unary_op(*op));
+                self.expr(value);
+            }
+            Expr::BinaryOp(left, op, right) => {
+                let mut indented = false;
+                self.expr(left);
+                let operator = self.next_token(end(left));
+                self.gap(end(left), operator.map(|t| t.start), " ", &mut indented);
+                self.push(binary_op(*op));
+                self.gap(operator.map(|t| t.end), start(right), " ", &mut indented);
+                self.expr(right);
+                if indented {
+                    self.indent -= 1;
+                }
+            }
+            Expr::AssertExpr(AssertStmt(cond, message), rest) => {
+                self.push("assert ");
+                self.expr(cond);
+                if let Some(message) = message {
+                    self.push(" : ");
+                    self.expr(message);
+                }
+                self.push(";");
+                self.newline();
+                self.expr(rest);
+            }
+            Expr::LocalExpr(binds, body) => {
+                self.push("local ");
+                self.binds(binds, self.after_symbol(start(expr), "local"));
+                self.push(";");
+                let binds_end = binds.last().and_then(|bind| end(&bind.value));
+                let after_comments = self.comments(binds_end, start(body));
+                if after_comments.is_some() || self.broken(start(expr), start(body)) {
+                    if self.blank_line(after_comments.or(binds_end), start(body)) {
+                        self.out.push('\n');
+                    }
+                    self.newline();
+                } else {
+                    self.push(" ");
+                }
+                self.expr(body);
+            }
+            Expr::Import(path) => {
+                self.push("import ");
+                self.push(&quote(&path.to_string_lossy()));
+            }
+            Expr::ImportStr(path) => {
+                self.push("importstr ");
+                self.push(&quote(&path.to_string_lossy()));
+            }
+            Expr::ErrorStmt(value) => {
+                self.push("error ");
+                self.expr(value);
+            }
+            Expr::Apply(function, args, tailstrict) => {
+                self.expr(function);
+                self.args(args, self.after_symbol(end(function), "("));
+                if *tailstrict {
+                    self.push(" tailstrict");
+                }
+            }
+            Expr::Index(value, index) => {
+                self.expr(value);
+                match &*index.0 {
+                    Expr::Str(name) if utils::is_identifier(name) => {
+                        self.push(".");
+                        self.push(name);
+                    }
+                    _ => {
+                        self.push("[");
+                        self.expr(index);
+                        self.push("]");
+                    }
+                }
+            }
+            Expr::Function(params, body) => {
+                self.push("function");
+                let keyword_end = start(expr).map(|pos| pos + "function".len());
+                self.params(params, self.after_symbol(keyword_end, "("));
+                self.push(" ");
+                self.expr(body);
+            }
+            Expr::Intrinsic(name) => {
+                self.push("$intrinsic(");
+                self.push(name);
+                self.push(")");
+            }
+            Expr::IfElse {
+                cond,
+                cond_then,
+                cond_else,
+            } => {
+                let mut indented = false;
+                self.push("if ");
+                self.expr(&cond.0);
+                let then = self.next_token(end(&cond.0));
+                self.gap(end(&cond.0), then.map(|t| t.start), " ", &mut indented);
+                self.push("then");
+                self.gap(then.map(|t| t.end), start(cond_then), " ", &mut indented);
+                self.expr(cond_then);
+                if let Some(cond_else) = cond_else {
+                    // `else` lines up with `if`.
+                    let keyword = self.next_token(end(cond_then));
+                    self.close(end(cond_then), keyword.map(|t| t.start), " ", indented);
+                    indented = false;
+                    self.push("else");
+                    self.gap(keyword.map(|t| t.end), start(cond_else), " ", &mut indented);
+                    self.expr(cond_else);
+                }
+                if indented {
+                    self.indent -= 1;
+                }
+            }
+            Expr::Slice(value, desc) => {
+                self.expr(value);
+                self.push("[");
+                if let Some(start) = &desc.start {
+                    self.expr(start);
+                }
+                self.push(":");
+                if let Some(end) = &desc.end {
+                    self.expr(end);
+                }
+                if let Some(step) = &desc.step {
+                    self.push(":");
+                    self.expr(step);
+                }
+                self.push("]");
+            }
+        }
+    }
+
+    /// Writes an object literal spanning the given offsets, which start at
+    /// its opening brace.
+    fn obj_body(&mut self, body: &ObjBody, start: Option<usize>, body_end: Option<usize>) {
+        match body {
+            ObjBody::MemberList(members)
+                if members.is_empty() && !self.has_comments(start, body_end) =>
+            {
+                self.push("{}")
+            }
+            ObjBody::MemberList(members) if self.multiline(start, body_end) => {
+                self.push("{");
+                self.indent += 1;
+                let mut pos = start.map(|pos| pos + 1);
+                for (i, member) in members.iter().enumerate() {
+                    let member_start = self.item_start(pos);
+                    self.item(pos, i == 0, |p| p.member(member, member_start));
+                    pos = member_end(member);
+                }
+                self.comments(pos, body_end);
+                self.indent -= 1;
+                self.newline();
+                self.push("}");
+            }
+            ObjBody::MemberList(members) => {
+                self.push("{ ");
+                let mut pos = start.map(|pos| pos + 1);
+                for (i, member) in members.iter().enumerate() {
+                    if i > 0 {
+                        self.push(", ");
+                    }
+                    self.member(member, self.item_start(pos));
+                    pos = member_end(member);
+                }
+                self.push(" }");
+            }
+            ObjBody::ObjComp(comp) => {
+                let mut indented = false;
+                self.push("{");
+                let mut pos = start.map(|pos| pos + 1);
+                for bind in &comp.pre_locals {
+                    let bind_start = self.item_start(pos);
+                    self.gap(pos, bind_start, " ", &mut indented);
+                    self.push("local ");
+                    self.bind(bind, self.after_symbol(bind_start, "local"));
+                    self.push(",");
+                    pos = end(&bind.value);
+                }
+                let key_start = self.item_start(pos);
+                self.gap(pos, key_start, " ", &mut indented);
+                self.push("[");
+                self.expr(&comp.key);
+                self.push("]: ");
+                self.expr(&comp.value);
+                pos = end(&comp.value);
+                for bind in &comp.post_locals {
+                    self.push(",");
+                    let bind_start = self.item_start(pos);
+                    self.gap(pos, bind_start, " ", &mut indented);
+                    self.push("local ");
+                    self.bind(bind, self.after_symbol(bind_start, "local"));
+                    pos = end(&bind.value);
+                }
+                let specs_end = self.comp_specs(&comp.compspecs, pos, &mut indented);
+                self.close(specs_end, body_end.map(|pos| pos - 1), " ", indented);
+                self.push("}");
+            }
+        }
+    }
+
+    /// Writes an object member, which starts at `start`.
+    fn member(&mut self, member: &Member, start: Option<usize>) {
+        match member {
+            Member::Field(field) => self.field(field, start),
+            Member::BindStmt(bind) => {
+                self.push("local ");
+                self.bind(bind, self.after_symbol(start, "local"));
+            }
+            Member::AssertStmt(AssertStmt(cond, message)) => {
+                self.push("assert ");
+                self.expr(cond);
+                if let Some(message) = message {
+                    self.push(" : ");
+                    self.expr(message);
+                }
+            }
+        }
+    }
+
+    fn field(&mut self, field: &FieldMember, start: Option<usize>) {
+        let name_end = match &field.name {
+            FieldName::Fixed(_) => self.next_token(start).map(|t| t.end),
+            FieldName::Dyn(name) => self.after_symbol(end(name), "]"),
+        };
+        match &field.name {
+            FieldName::Fixed(name) if utils::is_identifier(name) => self.push(name),
+            FieldName::Fixed(name) => self.push(&quote(name)),
+            FieldName::Dyn(name) => {
+                self.push("[");
+                self.expr(name);
+                self.push("]");
+            }
+        }
+        if let Some(params) = &field.params {
+            self.params(params, self.after_symbol(name_end, "("));
+        }
+        self.push(visibility(field.plus, field.visibility));
+        self.push(" ");
+        self.expr(&field.value);
+    }
+
+    /// Writes the binds of a `local` whose keyword ends at `from`.
+    fn binds(&mut self, binds: &[BindSpec], mut from: Option<usize>) {
+        for (i, bind) in binds.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.bind(bind, from);
+            from = self.item_start(end(&bind.value));
+        }
+    }
+
+    /// Writes a bind whose name is the next token after `from`.
+    fn bind(&mut self, bind: &BindSpec, from: Option<usize>) {
+        self.push(&bind.name);
+        if let Some(params) = &bind.params {
+            let name_end = self.next_token(from).map(|t| t.end);
+            self.params(params, self.after_symbol(name_end, "("));
+        }
+        self.push(" = ");
+        self.expr(&bind.value);
+    }
+
+    /// Writes a parameter list whose opening parenthesis ends at `open`.
+    fn params(&mut self, params: &ParamsDesc, open: Option<usize>) {
+        let mut indented = false;
+        self.push("(");
+        let mut pos = open;
+        for (i, param) in params.iter().enumerate() {
+            if i > 0 {
+                self.push(",");
+            }
+            let param_start = self.item_start(pos);
+            self.gap(
+                pos,
+                param_start,
+                if i > 0 { " " } else { "" },
+                &mut indented,
+            );
+            self.push(&param.0);
+            pos = self.next_token(param_start).map(|t| t.end);
+            if let Some(default) = &param.1 {
+                self.push("=");
+                self.expr(default);
+                pos = end(default);
+            }
+        }
+        self.close(pos, self.item_start(pos), "", indented);
+        self.push(")");
+    }
+
+    /// Writes the arguments of a call whose opening parenthesis ends at
+    /// `open`.
+    fn args(&mut self, args: &ArgsDesc, open: Option<usize>) {
+        let mut indented = false;
+        self.push("(");
+        let mut pos = open;
+        for (i, arg) in args.0.iter().enumerate() {
+            if i > 0 {
+                self.push(",");
+            }
+            let arg_start = self.item_start(pos);
+            self.gap(pos, arg_start, if i > 0 { " " } else { "" }, &mut indented);
+            if let Some(name) = arg.0.as_deref() {
+                self.push(name);
+                self.push("=");
+            }
+            self.expr(&arg.1);
+            pos = end(&arg.1);
+        }
+        self.close(pos, self.item_start(pos), "", indented);
+        self.push(")");
+    }
+
+    /// Writes the `for` and `if` specs that follow `pos` and returns where
+    /// the last one ends.
+    fn comp_specs(
+        &mut self,
+        specs: &[CompSpec],
+        mut pos: Option<usize>,
+        indented: &mut bool,
+    ) -> Option<usize> {
+        for spec in specs {
+            let keyword = self.item_start(pos);
+            self.gap(pos, keyword, " ", indented);
+            match spec {
+                CompSpec::ForSpec(for_spec) => {
+                    self.push("for ");
+                    self.push(&for_spec.0);
+                    self.push(" in ");
+                    self.expr(&for_spec.1);
+                    pos = end(&for_spec.1);
+                }
+                CompSpec::IfSpec(if_spec) => {
+                    self.push("if ");
+                    self.expr(&if_spec.0);
+                    pos = end(&if_spec.0);
+                }
+            }
+        }
+        pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn format(code: &str) -> String {
+        let tokens = crate::cst::lex(code);
+        let file = std::rc::Rc::new(std::path::PathBuf::from("test.jsonnet"));
+        let (ast, errors) = crate::parser::parse(code, &tokens, file);
+        assert_eq!(errors, vec![]);
+        let tree = crate::cst::Tree::new(tokens, &ast);
+        super::format(code, &tree, &ast).unwrap()
+    }
+
+    #[test]
+    fn format_object() {
+        let code = r#"{a:1,"b-c"::  "x",   'd' +: [1,2],
+f(x, y=2)::x+y}"#;
+        assert_eq!(
+            format(code),
+            r#"{
+  a: 1,
+  'b-c':: 'x',
+  d+: [1, 2],
+  f(x, y=2):: x + y,
+}
+"#
+        );
+    }
+
+    #[test]
+    fn format_locals_and_calls() {
+        let code = "local a=1,b=function(x)x*2;\nlocal c=std.length([a,b(a)]);\n{c:c,\ninline:{ x:if c>1 then 'y' else \"z\" }}";
+        assert_eq!(
+            format(code),
+            r#"local a = 1, b = function(x) x * 2;
+local c = std.length([a, b(a)]);
+{
+  c: c,
+  inline: { x: if c > 1 then 'y' else 'z' },
+}
+"#
+        );
+    }
+
+    #[test]
+    fn format_keeps_number_spelling() {
+        assert_eq!(
+            format("[2.0,1e3,1.50,-0.5,7]"),
+            "[2.0, 1e3, 1.50, -0.5, 7]\n"
+        );
+    }
+
+    #[test]
+    fn format_is_stable() {
+        let code = "{\n  a: [\n    x\n    for x in [1, 2]\n    if x > 1\n  ],\n  b: $.a[0:1],\n}\n";
+        let once = format(code);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn format_keeps_line_breaks() {
+        let code = r#"local f(a,
+      b=1) = a+b;
+{
+  call: f(
+    1,  // first
+    // second
+    b=2
+  ),
+  sum: 1 +
+       2 // two
+       + 3,
+  cond: if f(1) > 2
+    then 'a'
+    else 'b',
+  branches: if true then
+      'a'
+  else
+      'b',
+  squares: [x * x
+      for x in [1, 2] /* small */
+      if x > 1],
+  fields: { [k]: 1
+    for k in ['a'] },
+}
+"#;
+        assert_eq!(
+            format(code),
+            r#"local f(a,
+  b=1) = a + b;
+{
+  call: f(
+    1, // first
+    // second
+    b=2
+  ),
+  sum: 1 +
+    2 // two
+    + 3,
+  cond: if f(1) > 2
+    then 'a'
+  else 'b',
+  branches: if true then
+    'a'
+  else
+    'b',
+  squares: [x * x
+    for x in [1, 2] /* small */
+    if x > 1],
+  fields: { [k]: 1
+    for k in ['a'] },
+}
+"#
+        );
+        assert_eq!(format(&format(code)), format(code));
+    }
+
+    #[test]
+    fn format_keeps_comments() {
+        let code = r#"// License header
+
+local a = 1;  // one
+# two
+local b = 2;
+{
+  // leading
+  x: a,   /* trailing */
+
+  y: [
+    b, // element
+  ],
+  z: "it's",
+  t: |||
+    text
+  |||,
+  // last
+}
+"#;
+        assert_eq!(
+            format(code),
+            r#"// License header
+
+local a = 1; // one
+# two
+local b = 2;
+{
+  // leading
+  x: a, /* trailing */
+
+  y: [
+    b, // element
+  ],
+  z: "it's",
+  t: |||
+    text
+  |||,
+  // last
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,475 @@
+//! Hover information for std functions, locals and object fields, and the
+//! evaluated value of the expression under the cursor.
+
+use std::{path::Path, rc::Rc};
+
+use jrsonnet_parser::{
+    BinaryOpType, Expr, FieldMember, FieldName, LocExpr, Member, ObjBody, Visibility,
+};
+use lsp_types::{Hover, HoverContents, MarkupContent, MarkupKind, Position, Url};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+    fields::{self, Expression, Field, Resolver},
+    files::Files,
+    formatter,
+    scope::{self, Kind},
+    stdlib, utils,
+};
+
+pub fn hover(files: &Files, uri: &Url, position: Position) -> Option<Hover> {
+    let document = files.get(uri)?;
+    let offset = utils::position_to_offset(&document.text, position);
+    let root = Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document: document.clone(),
+    };
+    let (info, evaluated, start, end) = describe(files, &root, offset)?;
+    let value = evaluated
+        .and_then(|expr| Some((files.evaluation(uri)?, expr)))
+        .and_then(|(evaluation, expr)| {
+            let code = value_code(&document, evaluation.path(), &expr)?;
+            evaluation.value(&code)
+        });
+    let value = match (info, value) {
+        (Some(info), Some(value)) => format!("{}\n\n---\n\n```json\n{}\n```", info, value),
+        (Some(info), None) => info,
+        (None, Some(value)) => format!("```json\n{}\n```", value),
+        (None, None) => return None,
+    };
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: Some(utils::offset_range_to_range(&document.text, start, end)),
+    })
+}
+
+/// What is known about the name at `offset` without evaluating anything,
+/// the expression whose value is worth showing and the byte range of the
+/// name.
+fn describe(
+    files: &Files,
+    root: &Expression,
+    offset: usize,
+) -> Option<(Option<String>, Option<LocExpr>, usize, usize)> {
+    let document = &root.document;
+    let resolver = Resolver::new(files);
+    let path = cst::path_at(&document.ast, offset);
+    if let Some((target, name)) = fields::access(&path) {
+        let location = path.last()?.1.as_ref()?;
+        if is_std(target) {
+            let info = stdlib::find(name).map(|function| {
+                format!(
+                    "```jsonnet\n{}\n```\n\n{}",
+                    function.signature(),
+                    function.doc
+                )
+            });
+            return Some((info, None, location.1, location.2));
+        }
+        let info = resolver
+            .object(&root.with(target.clone()))
+            .and_then(|object| {
+                let field = object.lookup(name).pop()?;
+                Some(field_info(&resolver, &field, object.hidden(name)))
+            });
+        let access = path[path.len() - 2].clone();
+        return Some((info, Some(access), location.1, location.2));
+    }
+    if let Some(field) = fields::field_at(root, offset) {
+        let member = field.member();
+        let hidden = matches!(member.visibility, Visibility::Hidden);
+        let value = Some(member.value.clone()).filter(|_| member.params.is_none());
+        let info = field_info(&resolver, &field, hidden);
+        return Some((Some(info), value, field.start, field.end));
+    }
+
+    let analysis = scope::analyze(document);
+    let definition = &analysis.definitions[analysis.definition_at(offset)?];
+    let reference = analysis
+        .references
+        .iter()
+        .find(|r| r.start <= offset && offset <= r.end);
+    let (start, end) = reference.map_or((definition.start, definition.end), |r| (r.start, r.end));
+    let kind = match definition.kind {
+        Kind::Local => "local",
+        Kind::Parameter => "parameter",
+        Kind::ForVariable => "for variable",
+    };
+    let line_start = document.text[..definition.start]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let line_end = document.text[definition.start..]
+        .find('\n')
+        .map_or(document.text.len(), |i| definition.start + i);
+    let mut info = format!(
+        "```jsonnet\n{}\n```\n\n{} `{}`",
+        document.text[line_start..line_end].trim(),
+        kind,
+        definition.name
+    );
+    if let Some(comment) = comment_before(document, line_start) {
+        info.push_str("\n\n");
+        info.push_str(&comment);
+    }
+    let value = match (reference, path.last()) {
+        (Some(_), Some(var)) => Some((*var).clone()),
+        _ => definition
+            .value
+            .clone()
+            .filter(|_| definition.params.is_none()),
+    };
+    Some((Some(info), value, start, end))
+}
+
+/// Code that evaluates `expr` next to the document at `path`: the locals it
+/// uses, directly or through other locals, are repeated, and `self` and `$`
+/// become accesses into the imported document. Objects that cannot be
+/// reached from the top-level value that way, and `super`, are not
+/// supported, and neither are parameters and `for` variables, which have no
+/// value here.
+fn value_code(document: &Document, path: &Path, expr: &LocExpr) -> Option<String> {
+    let start = scope::start(expr);
+    let mut code = format!(
+        "local __top = import {};\n",
+        formatter::quote(path.to_str()?)
+    );
+    let analysis = scope::analyze_at(document, start);
+    let mut used = vec![false; analysis.definitions.len()];
+    let mut pending = vec![(start, scope::end(expr))];
+    while let Some((from, to)) = pending.pop() {
+        for reference in &analysis.references {
+            let definition = match reference.definition {
+                Some(definition) if from <= reference.start && reference.end <= to => definition,
+                _ => continue,
+            };
+            if used[definition] {
+                continue;
+            }
+            used[definition] = true;
+            let definition = &analysis.definitions[definition];
+            if let Some(value) = &definition.value {
+                pending.push((definition.start, scope::end(value)));
+            }
+        }
+    }
+    for &definition in analysis.visible.iter().filter(|&&d| used[d]) {
+        let definition = &analysis.definitions[definition];
+        match (definition.kind, &definition.value) {
+            (Kind::Local, Some(value)) => code.push_str(&format!(
+                "local {};\n",
+                rewrite(document, definition.start, scope::end(value))?
+            )),
+            _ => return None,
+        }
+    }
+    code.push_str(&rewrite(document, start, scope::end(expr))?);
+    Some(code)
+}
+
+/// The code between `start` and `end`, with `self` and `$` replaced by
+/// accesses into `__top`. Objects written in the code keep theirs.
+pub fn rewrite(document: &Document, start: usize, end: usize) -> Option<String> {
+    let text = &document.text;
+    let mut code = String::new();
+    let mut last = start;
+    for token in &document.cst.tokens {
+        if token.start < start || token.end > end {
+            continue;
+        }
+        let (outermost, is_super) = match (token.kind, token.text(text)) {
+            (TokenKind::Keyword, "self") => (false, false),
+            (TokenKind::Symbol, "$") => (true, false),
+            (TokenKind::Keyword, "super") => (false, true),
+            _ => continue,
+        };
+        let path = enclosing_object(document, token.start, outermost)?;
+        if scope::start(path[path.len() - 1]) >= start {
+            continue;
+        }
+        if is_super {
+            return None;
+        }
+        code.push_str(&text[last..token.start]);
+        code.push_str(&object_access(&path)?);
+        last = token.end;
+    }
+    code.push_str(&text[last..end]);
+    Some(code)
+}
+
+/// The expressions from the root down to the object `self` (or `$` if
+/// `outermost`) at `offset` refers to.
+fn enclosing_object(document: &Document, offset: usize, outermost: bool) -> Option<Vec<&LocExpr>> {
+    let mut path = cst::path_at(&document.ast, offset);
+    let in_body = |expr: &&LocExpr| match &*expr.0 {
+        Expr::Obj(_) => true,
+        Expr::ObjExtend(base, _) => offset >= scope::end(base),
+        _ => false,
+    };
+    let object = if outermost {
+        path.iter().position(in_body)?
+    } else {
+        path.iter().rposition(in_body)?
+    };
+    path.truncate(object + 1);
+    Some(path)
+}
+
+/// `__top` followed by the fields that lead down `path` to the object.
+fn object_access(path: &[&LocExpr]) -> Option<String> {
+    let mut access = "__top".to_string();
+    for pair in path.windows(2) {
+        let (parent, child) = (pair[0], pair[1]);
+        match &*parent.0 {
+            Expr::LocalExpr(_, body) | Expr::Parened(body) | Expr::AssertExpr(_, body)
+                if Rc::ptr_eq(&body.0, &child.0) => {}
+            // `self` is the combined object.
+            Expr::BinaryOp(_, BinaryOpType::Add, _) => {}
+            Expr::ObjExtend(base, _) if Rc::ptr_eq(&base.0, &child.0) => {}
+            Expr::Obj(ObjBody::MemberList(members))
+            | Expr::ObjExtend(_, ObjBody::MemberList(members)) => {
+                let name = members.iter().find_map(|member| match member {
+                    Member::Field(FieldMember {
+                        name: FieldName::Fixed(name),
+                        params: None,
+                        value,
+                        ..
+                    }) if Rc::ptr_eq(&value.0, &child.0) => Some(name),
+                    _ => None,
+                })?;
+                access.push_str(&format!("[{}]", formatter::quote(name)));
+            }
+            _ => return None,
+        }
+    }
+    Some(access)
+}
+
+fn is_std(expr: &LocExpr) -> bool {
+    matches!(&*expr.0, Expr::Var(name) if &**name == "std")
+}
+
+/// The field as it is written, like `name+::`, and what that means.
+fn field_info(resolver: &Resolver, field: &Field, hidden: bool) -> String {
+    let member = field.member();
+    let text = &field.layer.document.text;
+    let mut signature = text[field.start..field.end].to_string();
+    if let Some(params) = &member.params {
+        let params: Vec<&str> = params.iter().map(|param| &*param.0).collect();
+        signature.push_str(&format!("({})", params.join(", ")));
+    }
+    if member.plus {
+        signature.push('+');
+    }
+    signature.push_str(match member.visibility {
+        Visibility::Normal => ":",
+        Visibility::Hidden => "::",
+        Visibility::Unhide => ":::",
+    });
+    let mut description = if hidden {
+        "Hidden field"
+    } else {
+        "Visible field"
+    }
+    .to_string();
+    if let Some(kind) = resolver.field_kind(field) {
+        description.push_str(&format!(" of type `{}`", kind));
+    }
+    if let Visibility::Unhide = member.visibility {
+        description.push_str(", shown with `:::` even where it was hidden");
+    }
+    if member.plus {
+        description.push_str(", merged into the inherited value with `+:`");
+    }
+    format!("```jsonnet\n{}\n```\n\n{}.", signature, description)
+}
+
+/// The comment on the lines right above `line_start`, without the comment
+/// markers.
+fn comment_before(document: &Document, line_start: usize) -> Option<String> {
+    let tokens = &document.cst.tokens;
+    let text = &document.text;
+    let mut index = tokens.partition_point(|t| t.start < line_start);
+    let mut lines = vec![];
+    while index > 0 {
+        index -= 1;
+        let token = tokens[index];
+        match token.kind {
+            // A blank line ends the comment.
+            TokenKind::Whitespace if token.text(text).matches('\n').count() <= 1 => continue,
+            TokenKind::LineComment | TokenKind::BlockComment => {}
+            _ => break,
+        }
+        // Comments after code belong to that code.
+        let before = text[..token.start].rsplit('\n').next().unwrap_or_default();
+        if !before.trim().is_empty() {
+            break;
+        }
+        let comment = token.text(text);
+        let comment = if token.kind == TokenKind::LineComment {
+            comment.trim_start_matches("//").trim_start_matches('#')
+        } else {
+            comment.trim_start_matches("/*").trim_end_matches("*/")
+        };
+        lines.push(comment.trim());
+    }
+    lines.reverse();
+    let comment = lines.join("\n");
+    if comment.is_empty() {
+        None
+    } else {
+        Some(comment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use lsp_types::{HoverContents, Url};
+
+    use crate::{cst, document::Document, files::Files, scope, testing::TempDir, utils};
+
+    fn hover(code: &str, at: &str) -> Option<String> {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let position = utils::offset_to_position(code, code.find(at).unwrap());
+        match super::hover(&files, &uri, position)?.contents {
+            HoverContents::Markup(content) => Some(content.value),
+            _ => None,
+        }
+    }
+
+    /// The code that evaluates the expression `expression` written after
+    /// `before` in `code`.
+    fn value_code(code: &str, before: &str, expression: &str) -> Option<String> {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let document = Document::new(&uri, code.to_string());
+        let start = code.find(&format!("{}{}", before, expression)).unwrap() + before.len();
+        let expr = cst::descendants(&document.ast)
+            .into_iter()
+            .find(|expr| {
+                scope::start(expr) == start && scope::end(expr) == start + expression.len()
+            })
+            .unwrap()
+            .clone();
+        super::value_code(&document, Path::new("/test.jsonnet"), &expr)
+    }
+
+    #[test]
+    fn value_code_rewrites_self() {
+        let code = "local a = 1;\n{ b: { c: self.d, d: $.e + a }, e: 2, local l = self.e, f: l }\n+ { g: [{ h: self.i }], j: { k: self.e } }";
+        let header = "local __top = import '/test.jsonnet';\n";
+        assert_eq!(
+            value_code(code, "", "self.d").unwrap(),
+            format!("{}__top['b'].d", header)
+        );
+        assert_eq!(
+            value_code(code, "", "$.e + a").unwrap(),
+            format!("{}local a = 1;\n__top.e + a", header)
+        );
+        // Object locals are visible in the whole object.
+        assert_eq!(
+            value_code(code, "f: ", "l").unwrap(),
+            format!("{}local l = __top.e;\nl", header)
+        );
+        assert_eq!(
+            value_code(code, "k: ", "self.e").unwrap(),
+            format!("{}__top['j'].e", header)
+        );
+        // Objects in arrays are not reachable by field names.
+        assert_eq!(value_code(code, "", "self.i"), None);
+        // Objects written in the expression keep their own `self`.
+        assert_eq!(
+            value_code(code, "g: ", "[{ h: self.i }]").unwrap(),
+            format!("{}[{{ h: self.i }}]", header)
+        );
+    }
+
+    #[test]
+    fn value_code_in_functions() {
+        let code =
+            "local a = 1, b = a;\nlocal f(x) = { y: b + 2, z: x + b, w: [i for i in [1]] };\nf(0)";
+        let header = "local __top = import '/test.jsonnet';\n";
+        // Only the parameters the expression uses are in the way.
+        assert_eq!(
+            value_code(code, "y: ", "b + 2").unwrap(),
+            format!("{}local a = 1;\nlocal b = a;\nb + 2", header)
+        );
+        assert_eq!(
+            value_code(code, "w: ", "[i for i in [1]]").unwrap(),
+            format!("{}[i for i in [1]]", header)
+        );
+        assert_eq!(value_code(code, "z: ", "x + b"), None);
+    }
+
+    #[test]
+    fn stdlib() {
+        let value = hover("std.length([])", "length").unwrap();
+        assert!(value.starts_with("```jsonnet\nstd.length(x)\n```"));
+        assert!(hover("std.notAFunction", "notA").is_none());
+    }
+
+    #[test]
+    fn locals() {
+        let code = "// The answer.\n// Really.\nlocal a = 42;\n\n// Unrelated.\n\nlocal f(x) = x; // trailing\nlocal b = 1;\nf(a) + b";
+        assert_eq!(
+            hover(code, "a)").unwrap(),
+            "```jsonnet\nlocal a = 42;\n```\n\nlocal `a`\n\nThe answer.\nReally."
+        );
+        assert_eq!(
+            hover(code, "x)").unwrap(),
+            "```jsonnet\nlocal f(x) = x; // trailing\n```\n\nparameter `x`"
+        );
+        assert!(!hover(code, "b =").unwrap().contains("trailing\n\n"));
+        assert!(!hover(code, "b =").unwrap().contains("Unrelated"));
+    }
+
+    #[test]
+    fn fields() {
+        let code = "local o = { a:: 1, b+: {}, f(x)::: x } + { a: 2 };\n[o.a, o.b, o.f]";
+        assert_eq!(
+            hover(code, "a:: 1").unwrap(),
+            "```jsonnet\na::\n```\n\nHidden field of type `number`."
+        );
+        assert_eq!(
+            hover(code, "a, o").unwrap(),
+            "```jsonnet\na:\n```\n\nHidden field of type `number`."
+        );
+        assert_eq!(
+            hover(code, "b, o").unwrap(),
+            "```jsonnet\nb+:\n```\n\nVisible field of type `object`, merged into the inherited value with `+:`."
+        );
+        assert_eq!(
+            hover(code, "f]").unwrap(),
+            "```jsonnet\nf(x):::\n```\n\nVisible field of type `function`, shown with `:::` even where it was hidden."
+        );
+    }
+
+    #[test]
+    fn evaluated_value() {
+        let root = TempDir::new("hover");
+        let code = "local replicas = 3;\n{ spec: { replicas: replicas * 2 } }";
+        fs::write(root.join("main.jsonnet"), code).unwrap();
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+
+        let at = |needle: &str| {
+            let position = utils::offset_to_position(code, code.find(needle).unwrap());
+            match super::hover(&files, &uri, position).unwrap().contents {
+                HoverContents::Markup(content) => content.value,
+                _ => unreachable!(),
+            }
+        };
+        assert!(at("replicas:").ends_with("\n\n---\n\n```json\n6\n```"));
+        let spec = at("spec");
+        assert!(spec.contains("```json\n{\n") && spec.contains("\"replicas\": 6"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Links from `import` and `importstr` paths to the imported files.
+
+use std::path::Path;
+
+use jrsonnet_parser::Expr;
+use lsp_types::{Diagnostic, DiagnosticSeverity, DocumentLink, Url};
+
+use crate::{cst, document::Document, files::Files, scope, utils};
+
+/// The path of every import in the document with the byte range of its
+/// string literal.
+pub fn imports(document: &Document) -> Vec<(&Path, usize, usize)> {
+    let tokens = &document.cst.tokens;
+    cst::descendants(&document.ast)
+        .into_iter()
+        .filter_map(|expr| {
+            let path = match &*expr.0 {
+                Expr::Import(path) | Expr::ImportStr(path) => path,
+                _ => return None,
+            };
+            let (start, end) = (scope::start(expr), scope::end(expr));
+            let string = tokens
+                .iter()
+                .filter(|t| start <= t.start && t.end <= end)
+                .find(|t| t.kind == cst::TokenKind::String)?;
+            Some((path.as_path(), string.start, string.end))
+        })
+        .collect()
+}
+
+/// Links over the import paths that resolve to a file.
+pub fn links(files: &Files, uri: &Url) -> Vec<DocumentLink> {
+    let document = match files.get(uri) {
+        Some(document) => document,
+        None => return vec![],
+    };
+    imports(&document)
+        .into_iter()
+        .filter_map(|(path, start, end)| {
+            Some(DocumentLink {
+                range: utils::offset_range_to_range(&document.text, start, end),
+                target: Some(files.resolve(uri, path)?),
+                tooltip: None,
+                data: None,
+            })
+        })
+        .collect()
+}
+
+/// Warnings for the import paths that do not resolve to a file.
+pub fn diagnostics(files: &Files, uri: &Url, document: &Document) -> Vec<Diagnostic> {
+    imports(document)
+        .into_iter()
+        .filter(|(path, _, _)| files.resolve(uri, path).is_none())
+        .map(|(path, start, end)| Diagnostic {
+            range: utils::offset_range_to_range(&document.text, start, end),
+            severity: Some(DiagnosticSeverity::Warning),
+            message: format!("Cannot find `{}`", path.display()),
+            ..Diagnostic::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::Url;
+
+    use crate::{document::Document, files::Files, testing::TempDir, utils};
+
+    #[test]
+    fn links_and_unresolved() {
+        let root = TempDir::new("links");
+        fs::create_dir_all(root.join("vendor/k")).unwrap();
+        fs::write(root.join("data.txt"), "").unwrap();
+        fs::write(root.join("vendor/k/k.libsonnet"), "{}").unwrap();
+
+        let code = "local k = import 'k/k.libsonnet';\nlocal missing = import \"missing.libsonnet\";\nimportstr 'data.txt'";
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![root.join("vendor")]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+
+        let links: Vec<_> = super::links(&files, &uri)
+            .into_iter()
+            .map(|link| {
+                let start = utils::position_to_offset(code, link.range.start);
+                let end = utils::position_to_offset(code, link.range.end);
+                (&code[start..end], link.target.unwrap())
+            })
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (
+                    "'k/k.libsonnet'",
+                    Url::from_file_path(root.join("vendor/k/k.libsonnet")).unwrap()
+                ),
+                (
+                    "'data.txt'",
+                    Url::from_file_path(root.join("data.txt")).unwrap()
+                ),
+            ]
+        );
+
+        let document = files.get(&uri).unwrap();
+        let diagnostics = super::diagnostics(&files, &uri, &document);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Cannot find `missing.libsonnet`");
+        assert_eq!(diagnostics[0].range.start.line, 1);
+    }
+}
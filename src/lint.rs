@@ -0,0 +1,551 @@
+//! Diagnostics that can be told from the syntax tree alone, without
+//! evaluating the document.
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use jrsonnet_parser::{Expr, ObjBody};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Diagnostic, DiagnosticRelatedInformation,
+    DiagnosticSeverity, DiagnosticTag, Location, Range, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+    fields::{Expression, Field, Object},
+    scope::{self, Analysis, Definition, Kind},
+    utils,
+};
+
+/// Names that are bound in every document, with what they are. The
+/// evaluator only binds `std`: its other globals, like the `$std` alias
+/// other implementations use, can't be written as identifiers.
+const GLOBALS: &[(&str, &str)] = &[("std", "the standard library")];
+
+fn global(name: &str) -> Option<&'static str> {
+    GLOBALS
+        .iter()
+        .find(|(global, _)| *global == name)
+        .map(|(_, description)| *description)
+}
+
+pub fn diagnostics(document: &Rc<Document>, uri: &Url) -> Vec<Diagnostic> {
+    let analysis = scope::analyze(document);
+    let mut diagnostics = vec![];
+    diagnostics.extend(undefined(document, &analysis));
+    for definition in unused(&analysis) {
+        diagnostics.push(unused_diagnostic(document, definition));
+    }
+    diagnostics.extend(bindings(document, uri, &analysis));
+    diagnostics.extend(duplicate_fields(document, uri));
+    diagnostics
+}
+
+/// Quick fixes for the diagnostics in `range`: removing unused locals.
+pub fn actions(document: &Document, uri: &Url, range: Range) -> Vec<CodeActionOrCommand> {
+    let text = &document.text;
+    let (start, end) = (
+        utils::position_to_offset(text, range.start),
+        utils::position_to_offset(text, range.end),
+    );
+    let analysis = scope::analyze(document);
+    let mut actions = vec![];
+    for definition in unused(&analysis) {
+        if definition.end < start || end < definition.start {
+            continue;
+        }
+        let (remove_start, remove_end) = match removal(document, definition) {
+            Some(removal) => removal,
+            None => continue,
+        };
+        let edit = TextEdit {
+            range: utils::offset_range_to_range(text, remove_start, remove_end),
+            new_text: String::new(),
+        };
+        let mut changes = HashMap::new();
+        changes.insert(uri.clone(), vec![edit]);
+        actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+            title: format!(
+                "Remove unused {} `{}`",
+                unused_kind(definition),
+                definition.name
+            ),
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![unused_diagnostic(document, definition)]),
+            edit: Some(WorkspaceEdit {
+                changes: Some(changes),
+                ..WorkspaceEdit::default()
+            }),
+            is_preferred: Some(true),
+            ..CodeAction::default()
+        }));
+    }
+    actions
+}
+
+/// Variables that are not bound anywhere around their use, with the most
+/// similar name in scope as a suggestion.
+fn undefined(document: &Document, analysis: &scope::Analysis) -> Vec<Diagnostic> {
+    let text = &document.text;
+    let mut diagnostics = vec![];
+    for (reference, in_scope) in &analysis.unbound {
+        let reference = &analysis.references[*reference];
+        let name = &text[reference.start..reference.end];
+        if global(name).is_some() {
+            continue;
+        }
+        let candidates = in_scope
+            .iter()
+            .map(|&definition| analysis.definitions[definition].name.as_str())
+            .chain(GLOBALS.iter().map(|(global, _)| *global));
+        let mut message = format!("Unknown variable `{}`", name);
+        if let Some(suggestion) = suggest(name, candidates) {
+            message.push_str(&format!(", did you mean `{}`?", suggestion));
+        }
+        diagnostics.push(Diagnostic {
+            range: utils::offset_range_to_range(text, reference.start, reference.end),
+            severity: Some(DiagnosticSeverity::Error),
+            message,
+            ..Diagnostic::default()
+        });
+    }
+    diagnostics
+}
+
+/// Names bound twice in the same `local` chain, parameter list or object,
+/// and locals that hide another binding of the same name.
+fn bindings(document: &Document, uri: &Url, analysis: &Analysis) -> Vec<Diagnostic> {
+    let text = &document.text;
+    let describe = |kind: Kind| match kind {
+        Kind::Local => "local",
+        Kind::Parameter => "parameter",
+        Kind::ForVariable => "variable",
+    };
+    let mut diagnostics = vec![];
+    for definition in &analysis.definitions {
+        let range = utils::offset_range_to_range(text, definition.start, definition.end);
+        let name = &definition.name;
+        if definition.duplicate {
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::Error),
+                message: format!("Duplicate {} `{}`", describe(definition.kind), name),
+                ..Diagnostic::default()
+            });
+        } else if definition.kind != Kind::Local {
+            continue;
+        } else if let Some(shadowed) = definition.shadows {
+            let shadowed = &analysis.definitions[shadowed];
+            let shadowed_range = utils::offset_range_to_range(text, shadowed.start, shadowed.end);
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::Warning),
+                message: format!(
+                    "Local `{}` shadows the {} on line {}",
+                    name,
+                    describe(shadowed.kind),
+                    shadowed_range.start.line + 1
+                ),
+                related_information: Some(vec![DiagnosticRelatedInformation {
+                    location: Location::new(uri.clone(), shadowed_range),
+                    message: format!("`{}` is first bound here", name),
+                }]),
+                ..Diagnostic::default()
+            });
+        } else if let Some(description) = global(name) {
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::Warning),
+                message: format!("Local `{}` shadows {}", name, description),
+                ..Diagnostic::default()
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Fields defined more than once in the same object literal.
+fn duplicate_fields(document: &Rc<Document>, uri: &Url) -> Vec<Diagnostic> {
+    let root = Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document: document.clone(),
+    };
+    let text = &document.text;
+    let mut diagnostics = vec![];
+    for expr in cst::descendants(&root.expr) {
+        if !matches!(
+            &*expr.0,
+            Expr::Obj(ObjBody::MemberList(_)) | Expr::ObjExtend(_, ObjBody::MemberList(_))
+        ) {
+            continue;
+        }
+        let fields = Object {
+            layers: vec![root.with(expr.clone())],
+        }
+        .fields();
+        let mut seen: HashMap<&str, &Field> = HashMap::new();
+        for field in &fields {
+            let first = match seen.get(field.name()) {
+                Some(first) => first,
+                None => {
+                    seen.insert(field.name(), field);
+                    continue;
+                }
+            };
+            diagnostics.push(Diagnostic {
+                range: utils::offset_range_to_range(text, field.start, field.end),
+                severity: Some(DiagnosticSeverity::Error),
+                message: format!("Duplicate field `{}`", field.name()),
+                related_information: Some(vec![DiagnosticRelatedInformation {
+                    location: first.location(),
+                    message: format!("`{}` is first defined here", field.name()),
+                }]),
+                ..Diagnostic::default()
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Locals and parameters that are never referenced, other than by a local
+/// in its own value, like a recursive function. Names starting with `_` are
+/// unused on purpose.
+fn unused(analysis: &Analysis) -> Vec<&Definition> {
+    let used: HashSet<usize> = analysis
+        .references
+        .iter()
+        .filter_map(|reference| {
+            let index = reference.definition?;
+            let definition = &analysis.definitions[index];
+            let recursive = definition.value.as_ref().is_some_and(|value| {
+                definition.start <= reference.start && reference.end <= scope::end(value)
+            });
+            if recursive {
+                None
+            } else {
+                Some(index)
+            }
+        })
+        .collect();
+    analysis
+        .definitions
+        .iter()
+        .enumerate()
+        .filter(|(index, definition)| {
+            definition.kind != Kind::ForVariable
+                && !used.contains(index)
+                && !definition.name.starts_with('_')
+        })
+        .map(|(_, definition)| definition)
+        .collect()
+}
+
+/// What an unused definition is called in messages.
+fn unused_kind(definition: &Definition) -> &'static str {
+    match (definition.kind, &definition.value) {
+        (Kind::Parameter, _) => "parameter",
+        (_, Some(value)) if matches!(&*value.0, Expr::Import(_) | Expr::ImportStr(_)) => "import",
+        _ => "local",
+    }
+}
+
+fn unused_diagnostic(document: &Document, definition: &Definition) -> Diagnostic {
+    Diagnostic {
+        range: utils::offset_range_to_range(&document.text, definition.start, definition.end),
+        severity: Some(DiagnosticSeverity::Warning),
+        message: format!("Unused {} `{}`", unused_kind(definition), definition.name),
+        tags: Some(vec![DiagnosticTag::Unnecessary]),
+        ..Diagnostic::default()
+    }
+}
+
+/// The byte range to delete to remove a local. The `local` keyword goes
+/// with it unless other locals share it, and so does the separator that is
+/// no longer needed.
+fn removal(document: &Document, definition: &Definition) -> Option<(usize, usize)> {
+    let value = definition.value.as_ref()?;
+    let tokens = &document.cst.tokens;
+    let text = &document.text;
+    let before = tokens.partition_point(|t| t.end <= definition.start);
+    let previous = tokens[..before]
+        .iter()
+        .rev()
+        .find(|t| !t.kind.is_trivia())?;
+    let (index, count) = definition.chain?;
+
+    if index > 0 {
+        // `, b = 2` after another local.
+        return Some((previous.start, scope::end(value)));
+    }
+    let start = if count > 1 {
+        definition.start
+    } else if previous.kind == TokenKind::Keyword && previous.text(text) == "local" {
+        previous.start
+    } else {
+        return None;
+    };
+    // The `;` or `,` after the value and the whitespace up to what follows.
+    let mut end = scope::end(value);
+    let after = tokens.partition_point(|t| t.start < end);
+    let mut rest = tokens[after..]
+        .iter()
+        .skip_while(|t| t.kind == TokenKind::Whitespace);
+    if let Some(separator) = rest.next() {
+        if matches!(separator.text(text), ";" | ",") {
+            end = separator.end;
+            if let Some(whitespace) = rest.next().filter(|t| t.kind == TokenKind::Whitespace) {
+                end = whitespace.end;
+            }
+        }
+    }
+    Some((start, end))
+}
+
+/// The candidate closest to `name`, if it is close enough to be a typo:
+/// at most a third of its characters may differ.
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = name.chars().count() / 3;
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The number of characters to insert, delete, replace or swap with their
+/// neighbour to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // `d[i][j]` is the distance between the first `i` characters of `a` and
+    // the first `j` of `b`.
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use lsp_types::{CodeActionOrCommand, DiagnosticSeverity, DiagnosticTag, Range, Url};
+
+    use crate::{document::Document, utils};
+
+    /// The messages about undefined variables.
+    fn undefined(code: &str) -> Vec<String> {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let document = Rc::new(Document::new(&uri, code.to_string()));
+        super::diagnostics(&document, &uri)
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .filter(|message| message.starts_with("Unknown"))
+            .collect()
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(super::edit_distance("kitten", "sitting"), 3);
+        assert_eq!(super::edit_distance("", "abc"), 3);
+        assert_eq!(super::edit_distance("name", "name"), 0);
+        assert_eq!(super::edit_distance("nmae", "name"), 1);
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let code = r#"local replicas = 3;
+local labels(name) = { app: nmae };
+{
+  a: if true then replicas else replcias,
+  b: std.length(undefinedThing),
+  c: sdt.length([]),
+  d: [x for x in [1] if y > 0],
+}"#;
+        assert_eq!(
+            undefined(code),
+            [
+                "Unknown variable `nmae`, did you mean `name`?",
+                "Unknown variable `replcias`, did you mean `replicas`?",
+                "Unknown variable `undefinedThing`",
+                "Unknown variable `sdt`, did you mean `std`?",
+                "Unknown variable `y`",
+            ]
+        );
+        assert!(undefined("local a = 1; { b: a, c: self.b, d: $.c }").is_empty());
+    }
+
+    #[test]
+    fn globals() {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        for &(name, description) in super::GLOBALS {
+            assert!(undefined(&format!("{}.length([])", name)).is_empty());
+            let typo = format!("{}_.length([])", name);
+            assert_eq!(
+                undefined(&typo),
+                [format!(
+                    "Unknown variable `{}_`, did you mean `{}`?",
+                    name, name
+                )]
+            );
+            let code = format!("local {} = {{}}; {}", name, name);
+            let document = Rc::new(Document::new(&uri, code));
+            let messages: Vec<String> = super::diagnostics(&document, &uri)
+                .into_iter()
+                .map(|d| d.message)
+                .collect();
+            assert_eq!(
+                messages,
+                [format!("Local `{}` shadows {}", name, description)]
+            );
+        }
+    }
+
+    #[test]
+    fn unused_bindings() {
+        let code = r#"local k = import 'k.libsonnet';
+local used = 1, unused = 2;
+local f(x, y, _z) = x;
+local fact(n) = if n == 0 then 1 else n * fact(n - 1);
+{
+  local hidden = 3,
+  a: f(used, 0),
+}"#;
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let document = Rc::new(Document::new(&uri, code.to_string()));
+        let diagnostics = super::diagnostics(&document, &uri);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Unused import `k`",
+                "Unused local `unused`",
+                "Unused parameter `y`",
+                "Unused local `fact`",
+                "Unused local `hidden`",
+            ]
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.tags == Some(vec![DiagnosticTag::Unnecessary])));
+    }
+
+    #[test]
+    fn remove_unused() {
+        let remove = |code: &str, name: &str| -> Option<String> {
+            let uri = Url::parse("file:///test.jsonnet").unwrap();
+            let document = Document::new(&uri, code.to_string());
+            let offset = code.find(name).unwrap();
+            let position = utils::offset_to_position(code, offset);
+            let actions = super::actions(&document, &uri, Range::new(position, position));
+            let action = match actions.into_iter().next()? {
+                CodeActionOrCommand::CodeAction(action) => action,
+                CodeActionOrCommand::Command(_) => return None,
+            };
+            assert!(action.title.starts_with("Remove unused "));
+            let edit = &action.edit?.changes?[&uri][0];
+            let start = utils::position_to_offset(code, edit.range.start);
+            let end = utils::position_to_offset(code, edit.range.end);
+            Some(format!(
+                "{}{}{}",
+                &code[..start],
+                edit.new_text,
+                &code[end..]
+            ))
+        };
+        assert_eq!(
+            remove("local k = import 'k.libsonnet';\nlocal a = 1;\na", "k ").as_deref(),
+            Some("local a = 1;\na")
+        );
+        assert_eq!(
+            remove("local a = 1, b = 2; a", "b =").as_deref(),
+            Some("local a = 1; a")
+        );
+        assert_eq!(
+            remove("local a = 1, b = 2; b", "a =").as_deref(),
+            Some("local b = 2; b")
+        );
+        assert_eq!(
+            remove("{\n  local x = 1,\n  a: 2,\n}", "x").as_deref(),
+            Some("{\n  a: 2,\n}")
+        );
+        assert_eq!(
+            remove("{ local x = 1, local y = 2, [k]: y for k in ['a'] }", "x").as_deref(),
+            Some("{ local y = 2, [k]: y for k in ['a'] }")
+        );
+        let titles = |code: &str| -> Vec<String> {
+            let uri = Url::parse("file:///test.jsonnet").unwrap();
+            let document = Document::new(&uri, code.to_string());
+            let range = Range::new(
+                utils::offset_to_position(code, 0),
+                utils::offset_to_position(code, code.len()),
+            );
+            super::actions(&document, &uri, range)
+                .into_iter()
+                .filter_map(|action| match action {
+                    CodeActionOrCommand::CodeAction(action) => Some(action.title),
+                    CodeActionOrCommand::Command(_) => None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            titles("local k = import 'k.libsonnet';\nlocal f(n) = f(n);\n1"),
+            ["Remove unused import `k`", "Remove unused local `f`"]
+        );
+        // Parameters are part of the function's signature.
+        assert_eq!(remove("function(x) 1", "x"), None);
+    }
+
+    #[test]
+    fn shadowing_and_duplicates() {
+        let code = r#"local name = 'a';
+local std = {};
+local f(x, x) = x;
+local a = 1, a = 2;
+{
+  local name = 'b',
+  a: a,
+  'a': 2,
+  b: [name for name in [f(1, 2)]],
+}"#;
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let document = Rc::new(Document::new(&uri, code.to_string()));
+        let messages: Vec<(String, Option<DiagnosticSeverity>)> =
+            super::diagnostics(&document, &uri)
+                .into_iter()
+                .filter(|d| !d.message.starts_with("Unused"))
+                .map(|d| (d.message, d.severity))
+                .collect();
+        let error = |message: &str| (message.to_string(), Some(DiagnosticSeverity::Error));
+        let warning = |message: &str| (message.to_string(), Some(DiagnosticSeverity::Warning));
+        assert_eq!(
+            messages,
+            [
+                warning("Local `std` shadows the standard library"),
+                error("Duplicate parameter `x`"),
+                error("Duplicate local `a`"),
+                warning("Local `name` shadows the local on line 1"),
+                error("Duplicate field `a`"),
+            ]
+        );
+    }
+}
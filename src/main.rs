@@ -1,16 +1,47 @@
+mod completion;
+mod cst;
+mod definition;
+mod document;
+mod evaluate;
+mod fields;
+mod files;
+mod folding;
+mod formatter;
+mod hover;
+mod links;
+mod lint;
+mod parser;
+mod references;
+mod rename;
+mod scope;
+mod selection;
+mod semantic;
+mod signature;
+mod stdlib;
+mod symbols;
+#[cfg(test)]
+mod testing;
 mod utils;
+mod workspace;
 
-use jrsonnet_parser::{LocExpr, ParseError};
+use document::Document;
+use files::Files;
 
 use log::{error, trace, warn};
 use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
 use lsp_types::{
     notification::{Notification as _, *},
-    request::{Formatting, Request as RequestTrait},
+    request::{
+        CodeActionRequest, Completion, DocumentLinkRequest, DocumentSymbolRequest,
+        FoldingRangeRequest, Formatting, GotoDefinition, HoverRequest, PrepareRenameRequest,
+        References, RegisterCapability, Rename, Request as RequestTrait, SelectionRangeRequest,
+        SemanticTokensFullRequest, SemanticTokensRangeRequest, SignatureHelpRequest,
+        WorkspaceSymbol,
+    },
     OneOf, *,
 };
 
-use std::{collections::HashMap, panic, process};
+use std::{env, panic, path::PathBuf, process};
 
 type Error = Box<dyn std::error::Error>;
 
@@ -35,39 +66,107 @@ fn real_main() -> Result<(), Error> {
             TextDocumentSyncOptions {
                 open_close: Some(true),
                 change: Some(TextDocumentSyncKind::Full),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
                 ..TextDocumentSyncOptions::default()
             },
         )),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
         completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_string(), "/".to_string()]),
             ..CompletionOptions::default()
         }),
         definition_provider: Some(OneOf::<_, _>::Left(true)),
         document_formatting_provider: Some(OneOf::<_, _>::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
         document_link_provider: Some(DocumentLinkOptions {
             resolve_provider: Some(false),
             work_done_progress_options: WorkDoneProgressOptions::default(),
         }),
-        rename_provider: Some(OneOf::<_, _>::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        references_provider: Some(OneOf::<_, _>::Left(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        })),
         selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        semantic_tokens_provider: Some(
+            SemanticTokensOptions {
+                legend: semantic::legend(),
+                range: Some(true),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            }
+            .into(),
+        ),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+            retrigger_characters: None,
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
         ..ServerCapabilities::default()
     })
     .unwrap();
 
-    connection.initialize(capabilities)?;
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
 
-    App {
-        files: HashMap::new(),
+    let files = Files::new(library_paths(&params));
+    let mut roots = workspace_roots(&params);
+    roots.extend(files.jpath().iter().cloned());
+    let mut app = App {
+        files,
+        index: workspace::Index::new(roots),
         conn: connection,
-    }
-    .main();
+    };
+    app.watch_files(&params);
+    app.main();
 
     io_threads.join()?;
 
     Ok(())
 }
 
+/// Library search paths: the `jpath` initialization option, relative to the
+/// workspace root, followed by the `JSONNET_PATH` environment variable.
+fn library_paths(params: &InitializeParams) -> Vec<PathBuf> {
+    let root = params
+        .root_uri
+        .as_ref()
+        .and_then(|uri| uri.to_file_path().ok());
+    let configured = params
+        .initialization_options
+        .as_ref()
+        .and_then(|options| options.get("jpath"))
+        .and_then(|jpath| jpath.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.as_str())
+        .map(|path| match &root {
+            Some(root) => root.join(path),
+            None => PathBuf::from(path),
+        });
+    let environment = env::var_os("JSONNET_PATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    configured.chain(environment).collect()
+}
+
+/// The workspace folders, or the root if the client does not support
+/// multiple folders.
+fn workspace_roots(params: &InitializeParams) -> Vec<PathBuf> {
+    let uris: Vec<&Url> = match &params.workspace_folders {
+        Some(folders) => folders.iter().map(|folder| &folder.uri).collect(),
+        None => params.root_uri.iter().collect(),
+    };
+    uris.into_iter()
+        .filter_map(|uri| uri.to_file_path().ok())
+        .collect()
+}
+
 struct App {
-    files: HashMap<Url, (Result<LocExpr, ParseError>, String)>,
+    files: Files,
+    index: workspace::Index,
     conn: Connection,
 }
 impl App {
@@ -93,6 +192,37 @@ impl App {
             err.to_string(),
         ));
     }
+    /// Asks the client to report changes to Jsonnet files on disk, which
+    /// make what was read from them stale.
+    fn watch_files(&mut self, params: &InitializeParams) {
+        let dynamic = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched| watched.dynamic_registration);
+        if dynamic != Some(true) {
+            return;
+        }
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: "**/*.{jsonnet,libsonnet}".to_string(),
+                kind: None,
+            }],
+        };
+        let registrations = vec![Registration {
+            id: "watch-jsonnet-files".to_string(),
+            method: DidChangeWatchedFiles::METHOD.to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        }];
+        let request = Request::new(
+            "watch-jsonnet-files".to_string().into(),
+            RegisterCapability::METHOD.to_string(),
+            RegistrationParams { registrations },
+        );
+        trace!("Sending request: {:#?}", request);
+        self.conn.sender.send(Message::Request(request)).unwrap();
+    }
     fn main(&mut self) {
         while let Ok(msg) = self.conn.receiver.recv() {
             trace!("Message: {:#?}", msg);
@@ -136,29 +266,111 @@ impl App {
         let mut req = Some(req);
         if let Some((id, params)) = cast::<Formatting>(&mut req) {
             let changes = match self.files.get(&params.text_document.uri) {
-                Some((result, _code)) => match result {
-                    Ok(ast) => {
-                        error!("HELLOast {:?} ", ast);
-                        vec![TextEdit {
-                            range: Range {
-                                start: Position {
-                                    line: 0,
-                                    character: 0,
-                                },
-                                end: Position {
-                                    line: 0,
-                                    character: 0,
-                                },
-                            },
-                            new_text: "TODO: test".to_string(),
-                            ..TextEdit::default()
-                        }]
-                    }
-                    _ => vec![],
-                },
+                Some(document) if document.is_valid() => {
+                    formatter::edits(&document.text, &document.cst, &document.ast)
+                }
                 _ => vec![],
             };
             self.reply(Response::new_ok(id, changes));
+        } else if let Some((id, params)) = cast::<GotoDefinition>(&mut req) {
+            let TextDocumentPositionParams {
+                text_document,
+                position,
+            } = params.text_document_position_params;
+            let location = definition::find(&self.files, &text_document.uri, position);
+            self.reply(Response::new_ok(
+                id,
+                location.map(GotoDefinitionResponse::Scalar),
+            ));
+        } else if let Some((id, params)) = cast::<References>(&mut req) {
+            let locations = references::find(
+                &self.files,
+                &self.index,
+                &params.text_document_position.text_document.uri,
+                params.text_document_position.position,
+                params.context.include_declaration,
+            );
+            self.reply(Response::new_ok(id, Some(locations)));
+        } else if let Some((id, params)) = cast::<PrepareRenameRequest>(&mut req) {
+            let range = rename::prepare(&self.files, &params.text_document.uri, params.position);
+            self.reply(Response::new_ok(
+                id,
+                range.map(PrepareRenameResponse::Range),
+            ));
+        } else if let Some((id, params)) = cast::<Rename>(&mut req) {
+            let position = params.text_document_position;
+            match rename::rename(
+                &self.files,
+                &self.index,
+                &position.text_document.uri,
+                position.position,
+                &params.new_name,
+            ) {
+                Ok(edit) => self.reply(Response::new_ok(id, edit)),
+                Err(message) => self.reply(Response::new_err(
+                    id,
+                    ErrorCode::InvalidParams as i32,
+                    message,
+                )),
+            }
+        } else if let Some((id, params)) = cast::<Completion>(&mut req) {
+            let position = params.text_document_position;
+            let items =
+                completion::complete(&self.files, &position.text_document.uri, position.position);
+            self.reply(Response::new_ok(id, CompletionResponse::Array(items)));
+        } else if let Some((id, params)) = cast::<HoverRequest>(&mut req) {
+            let TextDocumentPositionParams {
+                text_document,
+                position,
+            } = params.text_document_position_params;
+            let hover = hover::hover(&self.files, &text_document.uri, position);
+            self.reply(Response::new_ok(id, hover));
+        } else if let Some((id, params)) = cast::<SignatureHelpRequest>(&mut req) {
+            let TextDocumentPositionParams {
+                text_document,
+                position,
+            } = params.text_document_position_params;
+            let help = signature::help(&self.files, &text_document.uri, position);
+            self.reply(Response::new_ok(id, help));
+        } else if let Some((id, params)) = cast::<DocumentSymbolRequest>(&mut req) {
+            let symbols = symbols::document(&self.files, &params.text_document.uri);
+            self.reply(Response::new_ok(
+                id,
+                DocumentSymbolResponse::Nested(symbols),
+            ));
+        } else if let Some((id, params)) = cast::<DocumentLinkRequest>(&mut req) {
+            let links = links::links(&self.files, &params.text_document.uri);
+            self.reply(Response::new_ok(id, Some(links)));
+        } else if let Some((id, params)) = cast::<CodeActionRequest>(&mut req) {
+            let uri = &params.text_document.uri;
+            let actions = self
+                .files
+                .get(uri)
+                .map(|document| lint::actions(&document, uri, params.range));
+            self.reply(Response::new_ok(id, actions));
+        } else if let Some((id, params)) = cast::<FoldingRangeRequest>(&mut req) {
+            let ranges = folding::ranges(&self.files, &params.text_document.uri);
+            self.reply(Response::new_ok(id, Some(ranges)));
+        } else if let Some((id, params)) = cast::<SelectionRangeRequest>(&mut req) {
+            let ranges =
+                selection::ranges(&self.files, &params.text_document.uri, params.positions);
+            self.reply(Response::new_ok(id, Some(ranges)));
+        } else if let Some((id, params)) = cast::<SemanticTokensFullRequest>(&mut req) {
+            let tokens = semantic::tokens(&self.files, &params.text_document.uri, None);
+            self.reply(Response::new_ok(
+                id,
+                tokens.map(SemanticTokensResult::Tokens),
+            ));
+        } else if let Some((id, params)) = cast::<SemanticTokensRangeRequest>(&mut req) {
+            let tokens =
+                semantic::tokens(&self.files, &params.text_document.uri, Some(params.range));
+            self.reply(Response::new_ok(
+                id,
+                tokens.map(SemanticTokensRangeResult::Tokens),
+            ));
+        } else if let Some((id, params)) = cast::<WorkspaceSymbol>(&mut req) {
+            let symbols = self.index.search(&self.files, &params.query);
+            self.reply(Response::new_ok(id, Some(symbols)));
         } else {
             let req = req.expect("internal error: req should have been wrapped in Some");
 
@@ -170,35 +382,77 @@ impl App {
         }
     }
     fn handle_notification(&mut self, req: Notification) -> Result<(), Error> {
-        let parser_settings = jrsonnet_parser::ParserSettings::default();
         match &*req.method {
             DidOpenTextDocument::METHOD => {
                 let params: DidOpenTextDocumentParams = serde_json::from_value(req.params)?;
-                let text = params.text_document.text;
-                let parsed = jrsonnet_parser::parse(&text, &parser_settings);
-                self.send_diagnostics(params.text_document.uri.clone(), &text, &parsed)?;
-                self.files.insert(params.text_document.uri, (parsed, text));
+                let uri = params.text_document.uri;
+                let document = Document::new(&uri, params.text_document.text);
+                self.files.insert(uri.clone(), document);
+                // New files are not on disk before the editor saves them.
+                self.update_index(&uri);
+                self.send_diagnostics(uri, true)?;
             }
             DidChangeTextDocument::METHOD => {
                 let params: DidChangeTextDocumentParams = serde_json::from_value(req.params)?;
                 if let Some(change) = params.content_changes.into_iter().last() {
-                    let parsed = jrsonnet_parser::parse(&change.text, &parser_settings);
-                    self.send_diagnostics(params.text_document.uri.clone(), &change.text, &parsed)?;
-                    self.files
-                        .insert(params.text_document.uri, (parsed, change.text));
+                    let uri = params.text_document.uri;
+                    let document = Document::new(&uri, change.text);
+                    self.files.insert(uri.clone(), document);
+                    self.send_diagnostics(uri, false)?;
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: DidSaveTextDocumentParams = serde_json::from_value(req.params)?;
+                let uri = params.text_document.uri;
+                self.files.invalidate(&uri);
+                self.update_index(&uri);
+                self.send_diagnostics(uri, true)?;
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(req.params)?;
+                let uri = params.text_document.uri;
+                self.files.close(&uri);
+                // The edits may not have been saved.
+                self.update_index(&uri);
+                self.notify(Notification::new(
+                    "textDocument/publishDiagnostics".into(),
+                    PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: vec![],
+                        version: None,
+                    },
+                ));
+            }
+            DidChangeWatchedFiles::METHOD => {
+                let params: DidChangeWatchedFilesParams = serde_json::from_value(req.params)?;
+                for change in params.changes {
+                    self.files.invalidate(&change.uri);
+                    self.update_index(&change.uri);
                 }
             }
             _ => (),
         }
         Ok(())
     }
-    fn send_diagnostics(
-        &mut self,
-        uri: Url,
-        code: &str,
-        _result: &Result<LocExpr, ParseError>,
-    ) -> Result<(), Error> {
-        let diagnostics = utils::parse(&code);
+    fn update_index(&mut self, uri: &Url) {
+        if let Ok(path) = uri.to_file_path() {
+            self.index.update(&path);
+        }
+    }
+    /// Publishes the problems found in the document. Evaluating it can take
+    /// long with large libraries, so that is only done with
+    /// `evaluate_document`, on open and save, not on every change.
+    fn send_diagnostics(&mut self, uri: Url, evaluate_document: bool) -> Result<(), Error> {
+        let document = match self.files.get(&uri) {
+            Some(document) => document,
+            None => return Ok(()),
+        };
+        let mut diagnostics = utils::parse(&document);
+        diagnostics.extend(links::diagnostics(&self.files, &uri, &document));
+        diagnostics.extend(lint::diagnostics(&document, &uri));
+        if evaluate_document {
+            diagnostics.extend(evaluate::diagnostics(&self.files, &uri));
+        }
         self.notify(Notification::new(
             "textDocument/publishDiagnostics".into(),
             PublishDiagnosticsParams {
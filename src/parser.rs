@@ -0,0 +1,1020 @@
+//! Error-tolerant Jsonnet parser.
+//!
+//! Unlike `jrsonnet_parser::parse`, this parser does not stop at the first
+//! syntax error. It records the error, puts an error node where an
+//! expression was expected and continues at the next separator, so features
+//! working on the AST keep working while a document is being edited. Every
+//! expression in the resulting tree carries its location.
+
+use std::{path::PathBuf, rc::Rc};
+
+use jrsonnet_parser::{
+    Arg, ArgsDesc, AssertStmt, BinaryOpType, BindSpec, CompSpec, Expr, ExprLocation, FieldMember,
+    FieldName, ForSpecData, IfSpecData, LiteralType, LocExpr, Member, ObjBody, ObjComp, Param,
+    ParamsDesc, SliceDesc, UnaryOpType, Visibility,
+};
+
+use crate::cst::{Token, TokenKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Parses the tokens of `code` as produced by [`crate::cst::lex`].
+///
+/// Where an expression is missing or malformed, the tree contains a variable
+/// with an empty name, which no source code can produce, as the AST has no
+/// error variant.
+pub fn parse(code: &str, tokens: &[Token], file: Rc<PathBuf>) -> (LocExpr, Vec<SyntaxError>) {
+    let mut parser = Parser {
+        code,
+        tokens: tokens
+            .iter()
+            .filter(|t| !t.kind.is_trivia())
+            .copied()
+            .collect(),
+        pos: 0,
+        prev_end: 0,
+        file,
+        errors: vec![],
+    };
+    let expr = parser.expr();
+    if parser.peek().is_some() {
+        let message = format!(
+            "unexpected {} after the end of the document",
+            parser.found()
+        );
+        parser.error_here(message);
+    }
+    (expr, parser.errors)
+}
+
+/// The value of a string literal token.
+pub fn string_value(text: &str) -> String {
+    if let Some(rest) = text.strip_prefix('@') {
+        let quote = &rest[..1];
+        let inner = &rest[1..rest.len() - 1];
+        return inner.replace(&quote.repeat(2), quote);
+    }
+    if text.starts_with("|||") {
+        return text_block_value(text);
+    }
+    unescape(&text[1..text.len() - 1])
+}
+
+fn text_block_value(text: &str) -> String {
+    let body = &text[3..];
+    let body = body.find('\n').map(|i| &body[i + 1..]).unwrap_or("");
+    // The last line holds the closing `|||`.
+    let body = &body[..body.rfind('\n').map(|i| i + 1).unwrap_or(0)];
+    let indent = body
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .unwrap_or("");
+    let mut out = String::with_capacity(body.len());
+    for line in body.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            out.push('\n');
+        } else {
+            out.push_str(line.strip_prefix(indent).unwrap_or(line));
+        }
+    }
+    out
+}
+
+fn unescape(inner: &str) -> String {
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let mut code = u32::from_str_radix(&hex, 16).unwrap_or(0xfffd);
+                if (0xd800..0xdc00).contains(&code) && chars.as_str().starts_with("\\u") {
+                    let low = u32::from_str_radix(chars.as_str().get(2..6).unwrap_or(""), 16);
+                    if let Ok(low @ 0xdc00..=0xdfff) = low {
+                        chars.by_ref().nth(5);
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                    }
+                }
+                out.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+            }
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn binary_op(text: &str) -> Option<(BinaryOpType, u8)> {
+    use BinaryOpType::*;
+    Some(match text {
+        "||" => (Or, 1),
+        "&&" => (And, 2),
+        "|" => (BitOr, 3),
+        "^" => (BitXor, 4),
+        "&" => (BitAnd, 5),
+        "==" => (Eq, 6),
+        "!=" => (Neq, 6),
+        "<" => (Lt, 7),
+        ">" => (Gt, 7),
+        "<=" => (Lte, 7),
+        ">=" => (Gte, 7),
+        "<<" => (Lhs, 8),
+        ">>" => (Rhs, 8),
+        "+" => (Add, 9),
+        "-" => (Sub, 9),
+        "*" => (Mul, 10),
+        "/" => (Div, 10),
+        "%" => (Mod, 10),
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    code: &'a str,
+    /// Tokens without trivia.
+    tokens: Vec<Token>,
+    pos: usize,
+    /// End of the last consumed token.
+    prev_end: usize,
+    file: Rc<PathBuf>,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn kind(&self) -> Option<TokenKind> {
+        self.peek().map(|t| t.kind)
+    }
+
+    fn text(&self) -> &'a str {
+        self.peek().map_or("", |t| t.text(self.code))
+    }
+
+    fn at(&self, text: &str) -> bool {
+        self.peek().is_some() && self.text() == text
+    }
+
+    fn at_close(&self) -> bool {
+        self.peek().is_none() || matches!(self.text(), ")" | "]" | "}")
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.tokens[self.pos];
+        self.pos += 1;
+        self.prev_end = token.end;
+        token
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        if self.at(text) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn start(&self) -> usize {
+        self.peek().map_or(self.prev_end, |t| t.start)
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(token) => {
+                let text = token.text(self.code);
+                match text.char_indices().nth(20) {
+                    Some((i, _)) => format!("`{}...`", &text[..i]),
+                    None => format!("`{}`", text),
+                }
+            }
+            None => "end of file".to_string(),
+        }
+    }
+
+    fn error(&mut self, message: String, start: usize, end: usize) {
+        // Only the first error at a position is useful, the others are
+        // follow-up errors of the same problem.
+        if self.errors.last().is_some_and(|e| e.start == start) {
+            return;
+        }
+        self.errors.push(SyntaxError {
+            message,
+            start,
+            end,
+        });
+    }
+
+    fn error_here(&mut self, message: String) {
+        let (start, end) = match self.peek() {
+            Some(token) => (token.start, token.end),
+            None => (self.prev_end, self.prev_end),
+        };
+        self.error(message, start, end);
+    }
+
+    fn expect(&mut self, text: &str) -> bool {
+        if self.eat(text) {
+            return true;
+        }
+        let message = format!("expected `{}`, found {}", text, self.found());
+        self.error_here(message);
+        false
+    }
+
+    /// Skips tokens up to one of `stop`, or a closing bracket that belongs
+    /// to an enclosing expression.
+    fn recover(&mut self, stop: &[&str]) {
+        let mut depth = 0usize;
+        while self.peek().is_some() {
+            let text = self.text();
+            if depth == 0 && stop.contains(&text) {
+                break;
+            }
+            match text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" if depth == 0 => break,
+                ")" | "]" | "}" => depth -= 1,
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    fn close(&mut self, text: &str) {
+        if !self.expect(text) {
+            self.recover(&[text]);
+            self.eat(text);
+        }
+    }
+
+    fn at_expr(&self) -> bool {
+        match self.kind() {
+            Some(TokenKind::Ident) | Some(TokenKind::Number) | Some(TokenKind::String) => true,
+            Some(TokenKind::Keyword) => !matches!(self.text(), "then" | "else" | "for" | "in"),
+            Some(TokenKind::Symbol) => {
+                matches!(self.text(), "(" | "[" | "{" | "$" | "-" | "+" | "!" | "~")
+            }
+            _ => false,
+        }
+    }
+
+    fn at_member(&self) -> bool {
+        match self.kind() {
+            Some(TokenKind::Ident) | Some(TokenKind::String) => true,
+            _ => matches!(self.text(), "[" | "local" | "assert"),
+        }
+    }
+
+    /// Consumes the separator of a list that ends with `close`. A missing
+    /// comma in front of something that can be the next item is reported
+    /// without skipping anything.
+    fn separator(&mut self, close: &str, at_item: fn(&Self) -> bool) {
+        if self.eat(",") || self.at(close) {
+            return;
+        }
+        let message = format!("expected `,` or `{}`, found {}", close, self.found());
+        self.error_here(message);
+        if !at_item(self) {
+            self.recover(&[",", close]);
+            self.eat(",");
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        if self.kind() == Some(TokenKind::Ident) {
+            let code = self.code;
+            Some(self.bump().text(code))
+        } else {
+            None
+        }
+    }
+
+    fn mk(&self, expr: Expr, start: usize) -> LocExpr {
+        LocExpr(
+            Rc::new(expr),
+            Some(ExprLocation(
+                self.file.clone(),
+                start,
+                self.prev_end.max(start),
+            )),
+        )
+    }
+
+    fn error_node(&self, start: usize, end: usize) -> LocExpr {
+        // A missing expression covers the gap it is missing from, so that it
+        // contains a cursor placed there.
+        let start = if start == end {
+            self.prev_end.min(start)
+        } else {
+            start
+        };
+        LocExpr(
+            Rc::new(Expr::Var("".into())),
+            Some(ExprLocation(self.file.clone(), start, end)),
+        )
+    }
+
+    fn expr(&mut self) -> LocExpr {
+        self.binary(1)
+    }
+
+    fn binary(&mut self, min: u8) -> LocExpr {
+        let start = self.start();
+        let mut left = self.unary();
+        while let Some((op, precedence)) = binary_op(self.text()) {
+            if precedence < min || self.kind() != Some(TokenKind::Symbol) {
+                break;
+            }
+            self.bump();
+            let right = self.binary(precedence + 1);
+            left = self.mk(Expr::BinaryOp(left, op, right), start);
+        }
+        left
+    }
+
+    fn unary(&mut self) -> LocExpr {
+        let start = self.start();
+        let op = match self.text() {
+            "-" => UnaryOpType::Minus,
+            "+" => UnaryOpType::Plus,
+            "!" => UnaryOpType::Not,
+            "~" => UnaryOpType::BitNot,
+            _ => return self.postfix(),
+        };
+        self.bump();
+        let value = self.unary();
+        self.mk(Expr::UnaryOp(op, value), start)
+    }
+
+    fn postfix(&mut self) -> LocExpr {
+        let start = self.start();
+        let mut expr = self.primary();
+        loop {
+            if self.eat(".") {
+                let name_start = self.start();
+                let name = match self.ident() {
+                    Some(name) => self.mk(Expr::Str(name.into()), name_start),
+                    None => {
+                        let message = format!("expected field name, found {}", self.found());
+                        self.error_here(message);
+                        self.error_node(name_start, name_start)
+                    }
+                };
+                expr = self.mk(Expr::Index(expr, name), start);
+            } else if self.eat("[") {
+                expr = self.index(expr, start);
+            } else if self.eat("(") {
+                let args = self.args();
+                let tailstrict = self.eat("tailstrict");
+                expr = self.mk(Expr::Apply(expr, args, tailstrict), start);
+            } else if self.eat("{") {
+                let body = self.object();
+                expr = self.mk(Expr::ObjExtend(expr, body), start);
+            } else {
+                return expr;
+            }
+        }
+    }
+
+    fn index(&mut self, target: LocExpr, start: usize) -> LocExpr {
+        let first = if self.at(":") || self.at("::") || self.at("]") {
+            None
+        } else {
+            Some(self.expr())
+        };
+        let slice = if self.eat(":") {
+            let end = if self.at(":") || self.at("]") {
+                None
+            } else {
+                Some(self.expr())
+            };
+            let step = if self.eat(":") && !self.at("]") {
+                Some(self.expr())
+            } else {
+                None
+            };
+            Some((end, step))
+        } else if self.eat("::") {
+            let step = if self.at("]") {
+                None
+            } else {
+                Some(self.expr())
+            };
+            Some((None, step))
+        } else {
+            None
+        };
+        let index = match (slice, first) {
+            (Some((end, step)), first) => {
+                self.close("]");
+                let desc = SliceDesc {
+                    start: first,
+                    end,
+                    step,
+                };
+                return self.mk(Expr::Slice(target, desc), start);
+            }
+            (None, Some(index)) => index,
+            (None, None) => {
+                let message = format!("expected index, found {}", self.found());
+                self.error_here(message);
+                self.error_node(self.start(), self.start())
+            }
+        };
+        self.close("]");
+        self.mk(Expr::Index(target, index), start)
+    }
+
+    fn args(&mut self) -> ArgsDesc {
+        let mut args = vec![];
+        while !self.at_close() {
+            let before = self.pos;
+            let named = self.kind() == Some(TokenKind::Ident)
+                && self
+                    .tokens
+                    .get(self.pos + 1)
+                    .is_some_and(|t| t.text(self.code) == "=");
+            let name = if named {
+                let name = self.ident().map(String::from);
+                self.bump();
+                name
+            } else {
+                None
+            };
+            let value = self.expr();
+            args.push(Arg(name, value));
+            self.separator(")", Self::at_expr);
+            if self.pos == before {
+                self.bump();
+            }
+        }
+        self.close(")");
+        ArgsDesc(args)
+    }
+
+    fn params(&mut self) -> ParamsDesc {
+        let mut params = vec![];
+        while !self.at_close() {
+            let before = self.pos;
+            match self.ident() {
+                Some(name) => {
+                    let default = if self.eat("=") {
+                        Some(self.expr())
+                    } else {
+                        None
+                    };
+                    params.push(Param(name.into(), default));
+                }
+                None => {
+                    let message = format!("expected parameter name, found {}", self.found());
+                    self.error_here(message);
+                }
+            }
+            self.separator(")", Self::at_expr);
+            if self.pos == before {
+                self.bump();
+            }
+        }
+        self.close(")");
+        ParamsDesc(Rc::new(params))
+    }
+
+    fn bind(&mut self) -> Option<BindSpec> {
+        let name = match self.ident() {
+            Some(name) => name,
+            None => {
+                let message = format!("expected variable name, found {}", self.found());
+                self.error_here(message);
+                return None;
+            }
+        };
+        let params = if self.eat("(") {
+            Some(self.params())
+        } else {
+            None
+        };
+        self.expect("=");
+        let value = self.expr();
+        Some(BindSpec {
+            name: name.into(),
+            params,
+            value,
+        })
+    }
+
+    fn comp_specs(&mut self) -> Vec<CompSpec> {
+        let mut specs = vec![];
+        loop {
+            if self.eat("for") {
+                let name = match self.ident() {
+                    Some(name) => name,
+                    None => {
+                        let message = format!("expected variable name, found {}", self.found());
+                        self.error_here(message);
+                        ""
+                    }
+                };
+                self.expect("in");
+                let value = self.expr();
+                specs.push(CompSpec::ForSpec(ForSpecData(name.into(), value)));
+            } else if self.eat("if") {
+                specs.push(CompSpec::IfSpec(IfSpecData(self.expr())));
+            } else {
+                return specs;
+            }
+        }
+    }
+
+    fn primary(&mut self) -> LocExpr {
+        let start = self.start();
+        let token = match self.peek() {
+            Some(token) => token,
+            None => {
+                self.error_here("expected expression, found end of file".to_string());
+                return self.error_node(start, self.code.len());
+            }
+        };
+        let text = token.text(self.code);
+        match token.kind {
+            TokenKind::Number => {
+                self.bump();
+                self.mk(Expr::Num(text.parse().unwrap_or(0.0)), start)
+            }
+            TokenKind::String => {
+                self.bump();
+                self.mk(Expr::Str(string_value(text).into()), start)
+            }
+            TokenKind::Ident => {
+                self.bump();
+                self.mk(Expr::Var(text.into()), start)
+            }
+            TokenKind::Keyword => self.keyword(text, start),
+            TokenKind::Symbol => {
+                let literal = match text {
+                    "$" => Some(LiteralType::Dollar),
+                    _ => None,
+                };
+                if let Some(literal) = literal {
+                    self.bump();
+                    return self.mk(Expr::Literal(literal), start);
+                }
+                if self.eat("(") {
+                    let inner = self.expr();
+                    self.close(")");
+                    return self.mk(Expr::Parened(inner), start);
+                }
+                if self.eat("{") {
+                    let body = self.object();
+                    return self.mk(Expr::Obj(body), start);
+                }
+                if self.eat("[") {
+                    return self.array(start);
+                }
+                let message = format!("expected expression, found {}", self.found());
+                self.error_here(message);
+                if !matches!(text, ")" | "]" | "}" | "," | ";") {
+                    self.bump();
+                }
+                self.error_node(start, self.prev_end.max(start))
+            }
+            TokenKind::Unknown => {
+                let message = if text.starts_with(['\'', '"', '@']) {
+                    "unterminated string".to_string()
+                } else if text.starts_with("|||") {
+                    "unterminated text block".to_string()
+                } else if text.starts_with("/*") {
+                    "unterminated comment".to_string()
+                } else {
+                    format!("unexpected character {}", self.found())
+                };
+                self.error_here(message);
+                self.bump();
+                self.error_node(start, self.prev_end)
+            }
+            TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment => {
+                unreachable!("trivia is filtered before parsing")
+            }
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, start: usize) -> LocExpr {
+        let literal = match keyword {
+            "self" => Some(LiteralType::This),
+            "super" => Some(LiteralType::Super),
+            "null" => Some(LiteralType::Null),
+            "true" => Some(LiteralType::True),
+            "false" => Some(LiteralType::False),
+            _ => None,
+        };
+        if let Some(literal) = literal {
+            self.bump();
+            return self.mk(Expr::Literal(literal), start);
+        }
+        match keyword {
+            "local" => {
+                self.bump();
+                let mut binds = vec![];
+                loop {
+                    match self.bind() {
+                        Some(bind) => binds.push(bind),
+                        None => self.recover(&[",", ";"]),
+                    }
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(";");
+                let body = self.expr();
+                self.mk(Expr::LocalExpr(binds, body), start)
+            }
+            "if" => {
+                self.bump();
+                let cond = self.expr();
+                self.expect("then");
+                let cond_then = self.expr();
+                let cond_else = if self.eat("else") {
+                    Some(self.expr())
+                } else {
+                    None
+                };
+                self.mk(
+                    Expr::IfElse {
+                        cond: IfSpecData(cond),
+                        cond_then,
+                        cond_else,
+                    },
+                    start,
+                )
+            }
+            "function" => {
+                self.bump();
+                let params = if self.expect("(") {
+                    self.params()
+                } else {
+                    ParamsDesc(Rc::new(vec![]))
+                };
+                let body = self.expr();
+                self.mk(Expr::Function(params, body), start)
+            }
+            "assert" => {
+                self.bump();
+                let cond = self.expr();
+                let message = if self.eat(":") {
+                    Some(self.expr())
+                } else {
+                    None
+                };
+                self.expect(";");
+                let rest = self.expr();
+                self.mk(Expr::AssertExpr(AssertStmt(cond, message), rest), start)
+            }
+            "error" => {
+                self.bump();
+                let value = self.expr();
+                self.mk(Expr::ErrorStmt(value), start)
+            }
+            "import" | "importstr" => {
+                self.bump();
+                let path = match self.peek() {
+                    Some(token) if token.kind == TokenKind::String => {
+                        let text = token.text(self.code);
+                        if text.starts_with("|||") {
+                            None
+                        } else {
+                            self.bump();
+                            Some(PathBuf::from(string_value(text)))
+                        }
+                    }
+                    _ => None,
+                };
+                match path {
+                    Some(path) if keyword == "import" => self.mk(Expr::Import(path), start),
+                    Some(path) => self.mk(Expr::ImportStr(path), start),
+                    None => {
+                        let message = format!("expected import path, found {}", self.found());
+                        self.error_here(message);
+                        self.error_node(start, self.prev_end)
+                    }
+                }
+            }
+            _ => {
+                let message = format!("expected expression, found {}", self.found());
+                self.error_here(message);
+                if !matches!(keyword, "then" | "else" | "for" | "in") {
+                    self.bump();
+                }
+                self.error_node(start, self.prev_end.max(start))
+            }
+        }
+    }
+
+    /// Parses an array after its opening bracket.
+    fn array(&mut self, start: usize) -> LocExpr {
+        if self.eat("]") {
+            return self.mk(Expr::Arr(vec![]), start);
+        }
+        let first = self.expr();
+        if self.at("for") {
+            let specs = self.comp_specs();
+            self.close("]");
+            return self.mk(Expr::ArrComp(first, specs), start);
+        }
+        let mut items = vec![first];
+        self.separator("]", Self::at_expr);
+        while !self.at_close() {
+            let before = self.pos;
+            items.push(self.expr());
+            self.separator("]", Self::at_expr);
+            if self.pos == before {
+                self.bump();
+            }
+        }
+        self.close("]");
+        self.mk(Expr::Arr(items), start)
+    }
+
+    /// Parses an object after its opening brace.
+    fn object(&mut self) -> ObjBody {
+        let start = self.prev_end;
+        let mut members = vec![];
+        let mut specs = None;
+        while !self.at_close() {
+            let before = self.pos;
+            if !self.at("for") {
+                if let Some(member) = self.member() {
+                    members.push(member);
+                }
+            }
+            if self.at("for") {
+                specs = Some(self.comp_specs());
+                break;
+            }
+            self.separator("}", Self::at_member);
+            if self.pos == before {
+                self.bump();
+            }
+        }
+        self.close("}");
+        match specs {
+            Some(specs) => self.obj_comp(start, members, specs),
+            None => ObjBody::MemberList(members),
+        }
+    }
+
+    fn member(&mut self) -> Option<Member> {
+        if self.eat("local") {
+            return self.bind().map(Member::BindStmt);
+        }
+        if self.eat("assert") {
+            let cond = self.expr();
+            let message = if self.eat(":") {
+                Some(self.expr())
+            } else {
+                None
+            };
+            return Some(Member::AssertStmt(AssertStmt(cond, message)));
+        }
+        let token = self.peek()?;
+        let name = match token.kind {
+            TokenKind::Ident => {
+                self.bump();
+                FieldName::Fixed(token.text(self.code).into())
+            }
+            TokenKind::String => {
+                self.bump();
+                FieldName::Fixed(string_value(token.text(self.code)).into())
+            }
+            _ if self.eat("[") => {
+                let name = self.expr();
+                self.close("]");
+                FieldName::Dyn(name)
+            }
+            _ => {
+                let message = format!("expected field name, found {}", self.found());
+                self.error_here(message);
+                return None;
+            }
+        };
+        let params = if self.eat("(") {
+            Some(self.params())
+        } else {
+            None
+        };
+        let plus = self.eat("+");
+        let visibility = match self.text() {
+            ":" => Some(Visibility::Normal),
+            "::" => Some(Visibility::Hidden),
+            ":::" => Some(Visibility::Unhide),
+            _ => None,
+        };
+        let value = match visibility {
+            Some(_) => {
+                self.bump();
+                self.expr()
+            }
+            None => {
+                let message = format!("expected `:`, found {}", self.found());
+                self.error_here(message);
+                self.error_node(self.prev_end, self.prev_end)
+            }
+        };
+        Some(Member::Field(FieldMember {
+            name,
+            plus,
+            params,
+            visibility: visibility.unwrap_or(Visibility::Normal),
+            value,
+        }))
+    }
+
+    fn obj_comp(
+        &mut self,
+        start: usize,
+        members: Vec<Member>,
+        compspecs: Vec<CompSpec>,
+    ) -> ObjBody {
+        let fields: Vec<_> = members
+            .iter()
+            .filter(|m| !matches!(m, Member::BindStmt(_)))
+            .collect();
+        let valid = match fields.as_slice() {
+            [Member::Field(field)] => matches!(field.name, FieldName::Dyn(_)),
+            _ => false,
+        };
+        if !valid {
+            self.error(
+                "object comprehensions need exactly one field with a computed name like `[key]: value`"
+                    .to_string(),
+                start,
+                self.prev_end,
+            );
+            return ObjBody::MemberList(members);
+        }
+        let mut pre_locals = vec![];
+        let mut post_locals = vec![];
+        let mut field = None;
+        for member in members {
+            match member {
+                Member::BindStmt(bind) if field.is_none() => pre_locals.push(bind),
+                Member::BindStmt(bind) => post_locals.push(bind),
+                Member::Field(FieldMember {
+                    name: FieldName::Dyn(key),
+                    value,
+                    ..
+                }) => field = Some((key, value)),
+                _ => {}
+            }
+        }
+        let (key, value) = field.expect("comprehension was validated to have a field");
+        ObjBody::ObjComp(ObjComp {
+            pre_locals,
+            key,
+            value,
+            post_locals,
+            compspecs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, rc::Rc};
+
+    use jrsonnet_parser::{Expr, LocExpr, Member, ObjBody};
+
+    use super::SyntaxError;
+    use crate::cst;
+
+    fn parse(code: &str) -> (LocExpr, Vec<SyntaxError>) {
+        super::parse(
+            code,
+            &cst::lex(code),
+            Rc::new(PathBuf::from("test.jsonnet")),
+        )
+    }
+
+    fn fields(expr: &LocExpr) -> Vec<String> {
+        match &*expr.0 {
+            Expr::Obj(ObjBody::MemberList(members)) => members
+                .iter()
+                .filter_map(|m| match m {
+                    Member::Field(field) => Some(format!("{:?}", field.name)),
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!("not an object: {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn parse_valid() {
+        let code = r#"
+local a = import 'a.libsonnet', f(x, y=2) = x * y + 1;
+{
+  [k]: v for k in ['x'] if k != 'y'
+} + {
+  a: a.b[1:2], 'b' +:: [x for x in std.range(0, 3)],
+  c(p):: if p then -p else f(p, y=3) tailstrict,
+  local l = $.a,
+  assert self.a != null : 'message',
+  d: |||
+    text
+  |||,
+  e: super.e { x: 1 },
+}
+"#;
+        let (expr, errors) = parse(code);
+        assert_eq!(errors, vec![]);
+        match &*expr.0 {
+            Expr::LocalExpr(binds, _) => {
+                assert_eq!(binds.len(), 2);
+                assert!(binds[1].params.is_some());
+            }
+            _ => panic!("expected local: {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn string_values() {
+        assert_eq!(super::string_value(r#"'a\'b\n'"#), "a'b\n");
+        assert_eq!(super::string_value(r#""ü😀""#), "ü😀");
+        assert_eq!(super::string_value("@'a''b\\n'"), "a'b\\n");
+        assert_eq!(
+            super::string_value("|||\n    a\n\n      b\n  |||"),
+            "a\n\n  b\n"
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let (expr, errors) = parse("1 + 2 * 3 == 7 && !x.y");
+        assert_eq!(errors, vec![]);
+        match &*expr.0 {
+            Expr::BinaryOp(left, jrsonnet_parser::BinaryOpType::And, _) => match &*left.0 {
+                Expr::BinaryOp(_, jrsonnet_parser::BinaryOpType::Eq, _) => {}
+                _ => panic!("unexpected: {:?}", left),
+            },
+            _ => panic!("unexpected: {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn recover_from_errors() {
+        let code = r#"{
+  a: 1
+  b: ,
+  c: [1 2],
+  d: self.,
+  e: 5,
+}"#;
+        let (expr, errors) = parse(code);
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "expected `,` or `}`, found `b`",
+                "expected expression, found `,`",
+                "expected `,` or `]`, found `2`",
+                "expected field name, found `,`",
+            ]
+        );
+        assert_eq!(fields(&expr).len(), 5);
+    }
+
+    #[test]
+    fn locations() {
+        let code = "local x = 1; x.y";
+        let (expr, _) = parse(code);
+        let location = expr.1.as_ref().unwrap();
+        assert_eq!((location.1, location.2), (0, code.len()));
+        match &*expr.0 {
+            Expr::LocalExpr(_, body) => match &*body.0 {
+                Expr::Index(target, field) => {
+                    assert_eq!(target.1.as_ref().unwrap().1, 13);
+                    let field = field.1.as_ref().unwrap();
+                    assert_eq!(&code[field.1..field.2], "y");
+                }
+                _ => panic!("unexpected: {:?}", body),
+            },
+            _ => panic!("unexpected: {:?}", expr),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Find all references.
+
+use std::collections::{HashMap, HashSet};
+
+use jrsonnet_parser::{BinaryOpType, Expr};
+use lsp_types::{Location, Position, Url};
+
+use crate::{
+    cst,
+    fields::{self, Expression, Field, Resolver},
+    files::Files,
+    scope::{self, Analysis},
+    utils,
+    workspace::Index,
+};
+
+/// What a name refers to.
+pub enum Target {
+    /// A local of the document, as an index into the definitions of its
+    /// analysis.
+    Local(Analysis, usize),
+    /// The fields that define an object member.
+    Fields(Vec<Field>),
+}
+
+/// The target of the name at `offset` and the byte range of that name.
+pub fn target_at(
+    resolver: &Resolver,
+    root: &Expression,
+    offset: usize,
+) -> Option<(Target, usize, usize)> {
+    let path = cst::path_at(&root.expr, offset);
+    if let Some((target, name)) = fields::access(&path) {
+        let fields = resolver.object(&root.with(target.clone()))?.defining(name);
+        let location = path.last()?.1.as_ref()?;
+        if fields.is_empty() {
+            return None;
+        }
+        return Some((Target::Fields(fields), location.1, location.2));
+    }
+    if let Some(field) = fields::field_at(root, offset) {
+        let (start, end) = (field.start, field.end);
+        return Some((Target::Fields(vec![field]), start, end));
+    }
+    let analysis = scope::analyze(&root.document);
+    let definition = analysis.definition_at(offset)?;
+    let (start, end) = analysis
+        .references
+        .iter()
+        .find(|r| r.start <= offset && offset <= r.end)
+        .map(|r| (r.start, r.end))
+        .unwrap_or_else(|| {
+            let definition = &analysis.definitions[definition];
+            (definition.start, definition.end)
+        });
+    Some((Target::Local(analysis, definition), start, end))
+}
+
+/// The names of all field accesses in the workspace that resolve to one of
+/// `targets`, as the document and byte range of the name.
+pub fn accesses(
+    files: &Files,
+    index: &Index,
+    resolver: &Resolver,
+    targets: &[Field],
+) -> Vec<(Expression, usize, usize)> {
+    let name = match targets.first() {
+        Some(field) => field.name(),
+        None => return vec![],
+    };
+    let defining: Vec<Url> = targets
+        .iter()
+        .map(|field| field.layer.uri.clone())
+        .collect();
+    let mut accesses = vec![];
+    for document in importers(files, index, &defining) {
+        for expr in cst::descendants(&document.expr) {
+            let (target, index) = match &*expr.0 {
+                Expr::Index(target, index) => (target, index),
+                _ => continue,
+            };
+            match &*index.0 {
+                Expr::Str(accessed) if &**accessed == name => {}
+                _ => continue,
+            }
+            let resolves_to_target =
+                resolver
+                    .object(&document.with(target.clone()))
+                    .is_some_and(|object| {
+                        object
+                            .defining(name)
+                            .iter()
+                            .any(|field| targets.iter().any(|target| target.is(field)))
+                    });
+            if let (true, Some(location)) = (resolves_to_target, &index.1) {
+                accesses.push((document.clone(), location.1, location.2));
+            }
+        }
+    }
+    accesses
+}
+
+/// `targets` together with the fields they override or are overridden by,
+/// in objects combined with `+` or object extension anywhere in the
+/// workspace.
+pub fn overrides(
+    files: &Files,
+    index: &Index,
+    resolver: &Resolver,
+    mut targets: Vec<Field>,
+) -> Vec<Field> {
+    let name = match targets.first() {
+        Some(field) => field.name().to_string(),
+        None => return targets,
+    };
+    let defining: Vec<Url> = targets
+        .iter()
+        .map(|field| field.layer.uri.clone())
+        .collect();
+    for document in importers(files, index, &defining) {
+        for expr in cst::descendants(&document.expr) {
+            if !matches!(
+                &*expr.0,
+                Expr::BinaryOp(_, BinaryOpType::Add, _) | Expr::ObjExtend(..)
+            ) {
+                continue;
+            }
+            let layers = match resolver.object(&document.with(expr.clone())) {
+                Some(object) => object.lookup(&name),
+                None => continue,
+            };
+            if !layers
+                .iter()
+                .any(|layer| targets.iter().any(|target| target.is(layer)))
+            {
+                continue;
+            }
+            for layer in layers {
+                if !targets.iter().any(|target| target.is(&layer)) {
+                    targets.push(layer);
+                }
+            }
+        }
+    }
+    targets
+}
+
+pub fn find(
+    files: &Files,
+    index: &Index,
+    uri: &Url,
+    position: Position,
+    include_declaration: bool,
+) -> Vec<Location> {
+    let document = match files.get(uri) {
+        Some(document) => document,
+        None => return vec![],
+    };
+    let offset = utils::position_to_offset(&document.text, position);
+    let root = Expression {
+        uri: uri.clone(),
+        document: document.clone(),
+        expr: document.ast.clone(),
+    };
+    let resolver = Resolver::new(files);
+    let location = |document: &Expression, start, end| Location {
+        uri: document.uri.clone(),
+        range: utils::offset_range_to_range(&document.document.text, start, end),
+    };
+
+    let mut locations = vec![];
+    match target_at(&resolver, &root, offset) {
+        Some((Target::Local(analysis, definition), _, _)) => {
+            if include_declaration {
+                let definition = &analysis.definitions[definition];
+                locations.push(location(&root, definition.start, definition.end));
+            }
+            locations.extend(
+                analysis
+                    .references
+                    .iter()
+                    .filter(|reference| reference.definition == Some(definition))
+                    .map(|reference| location(&root, reference.start, reference.end)),
+            );
+        }
+        Some((Target::Fields(fields), _, _)) => {
+            if include_declaration {
+                locations.extend(fields.iter().map(Field::location));
+            }
+            locations.extend(
+                accesses(files, index, &resolver, &fields)
+                    .iter()
+                    .map(|(document, start, end)| location(document, *start, *end)),
+            );
+        }
+        None => {}
+    }
+    locations
+}
+
+/// The documents among the open ones and the workspace files on disk, and
+/// everything they import, that are one of `targets` or import one of them,
+/// directly or indirectly.
+fn importers(files: &Files, index: &Index, targets: &[Url]) -> Vec<Expression> {
+    let mut pending: Vec<Url> = files
+        .opened()
+        .cloned()
+        .chain(
+            index
+                .paths()
+                .into_iter()
+                .filter_map(|path| Url::from_file_path(path).ok()),
+        )
+        .collect();
+    let mut seen: HashSet<Url> = pending.iter().cloned().collect();
+    let mut imported_by: HashMap<Url, Vec<Url>> = HashMap::new();
+    let mut documents = vec![];
+    while let Some(uri) = pending.pop() {
+        let document = match files.get(&uri) {
+            Some(document) => document,
+            None => continue,
+        };
+        for expr in cst::descendants(&document.ast) {
+            if let Expr::Import(path) = &*expr.0 {
+                if let Some(imported) = files.resolve(&uri, path) {
+                    imported_by
+                        .entry(imported.clone())
+                        .or_default()
+                        .push(uri.clone());
+                    if seen.insert(imported.clone()) {
+                        pending.push(imported);
+                    }
+                }
+            }
+        }
+        documents.push((uri, document));
+    }
+
+    // Follow the imports backwards from the targets.
+    let mut pending: Vec<&Url> = targets.iter().collect();
+    let mut reached: HashSet<&Url> = pending.iter().copied().collect();
+    while let Some(uri) = pending.pop() {
+        for importer in imported_by.get(uri).into_iter().flatten() {
+            if reached.insert(importer) {
+                pending.push(importer);
+            }
+        }
+    }
+    documents
+        .into_iter()
+        .filter(|(uri, _)| reached.contains(uri))
+        .map(|(uri, document)| Expression {
+            expr: document.ast.clone(),
+            uri,
+            document,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::{Location, Url};
+
+    use crate::{document::Document, files::Files, testing::TempDir, utils, workspace::Index};
+
+    fn lines(locations: Vec<Location>) -> Vec<(String, u32, u32)> {
+        let mut lines: Vec<_> = locations
+            .into_iter()
+            .map(|location| {
+                let file = location.uri.path().rsplit('/').next().unwrap().to_string();
+                (
+                    file,
+                    location.range.start.line,
+                    location.range.start.character,
+                )
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn locals() {
+        let code = "local a = 1;\nlocal f(a) = a;\n[a, f(a)]";
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let position = utils::offset_to_position(code, 6);
+
+        let file = || "test.jsonnet".to_string();
+        assert_eq!(
+            lines(super::find(
+                &files,
+                &Index::new(vec![]),
+                &uri,
+                position,
+                false
+            )),
+            vec![(file(), 2, 1), (file(), 2, 6)]
+        );
+        assert_eq!(
+            lines(super::find(
+                &files,
+                &Index::new(vec![]),
+                &uri,
+                position,
+                true
+            )),
+            vec![(file(), 0, 6), (file(), 2, 1), (file(), 2, 6)]
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let root = TempDir::new("references");
+        let lib_code = "{\n  a: 1,\n  b: self.a,\n  c: { a: 2 },\n}";
+        fs::write(root.join("lib.libsonnet"), lib_code).unwrap();
+        let lib = Url::from_file_path(root.join("lib.libsonnet")).unwrap();
+        // Files on disk that are not open, importing the library directly,
+        // through another file or not at all.
+        fs::write(
+            root.join("other.jsonnet"),
+            "local lib = import 'lib.libsonnet';\nlib.b + lib.a",
+        )
+        .unwrap();
+        fs::write(
+            root.join("wrapper.libsonnet"),
+            "{ lib: import 'lib.libsonnet' }",
+        )
+        .unwrap();
+        fs::write(
+            root.join("deep.jsonnet"),
+            "(import 'wrapper.libsonnet').lib.a",
+        )
+        .unwrap();
+        fs::write(root.join("unrelated.jsonnet"), "{ a: 1 }.a").unwrap();
+        let index = Index::new(vec![root.to_path_buf()]);
+
+        let code = "local lib = import 'lib.libsonnet';\n[lib.a, lib.c.a, (lib + { a: 3 }).a]";
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+
+        let from_definition = utils::offset_to_position(lib_code, lib_code.find('a').unwrap());
+        let main = || "main.jsonnet".to_string();
+        let lib_file = || "lib.libsonnet".to_string();
+        assert_eq!(
+            lines(super::find(&files, &index, &lib, from_definition, true)),
+            vec![
+                ("deep.jsonnet".to_string(), 0, 33),
+                (lib_file(), 1, 2),
+                (lib_file(), 2, 10),
+                (main(), 1, 5),
+                ("other.jsonnet".to_string(), 1, 12),
+            ]
+        );
+
+        let from_access = utils::offset_to_position(code, code.find("a, lib").unwrap());
+        assert_eq!(
+            lines(super::find(&files, &index, &uri, from_access, false)),
+            vec![
+                ("deep.jsonnet".to_string(), 0, 33),
+                (lib_file(), 2, 10),
+                (main(), 1, 5),
+                ("other.jsonnet".to_string(), 1, 12),
+            ]
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Rename of locals, parameters and object fields.
+//!
+//! Locals must be renamed to identifiers. Fields can have any name, it is
+//! quoted wherever it is not a valid identifier.
+
+use std::collections::HashMap;
+
+use lsp_types::{Position, Range, TextEdit, Url, WorkspaceEdit};
+
+use crate::{
+    cst::TokenKind,
+    document::Document,
+    fields::{Expression, Resolver},
+    files::Files,
+    formatter,
+    references::{self, Target},
+    scope::{self, Analysis},
+    utils,
+    workspace::Index,
+};
+
+fn root(files: &Files, uri: &Url) -> Option<Expression> {
+    let document = files.get(uri)?;
+    Some(Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document,
+    })
+}
+
+/// The range of the name at `position` if it can be renamed.
+pub fn prepare(files: &Files, uri: &Url, position: Position) -> Option<Range> {
+    let root = root(files, uri)?;
+    let offset = utils::position_to_offset(&root.document.text, position);
+    let (_, start, end) = references::target_at(&Resolver::new(files), &root, offset)?;
+    Some(utils::offset_range_to_range(
+        &root.document.text,
+        start,
+        end,
+    ))
+}
+
+pub fn rename(
+    files: &Files,
+    index: &Index,
+    uri: &Url,
+    position: Position,
+    new_name: &str,
+) -> Result<WorkspaceEdit, String> {
+    let root = root(files, uri).ok_or_else(|| format!("Unknown document {}", uri))?;
+    let offset = utils::position_to_offset(&root.document.text, position);
+    let resolver = Resolver::new(files);
+    let target = match references::target_at(&resolver, &root, offset) {
+        Some((target, _, _)) => target,
+        None => return Err("There is nothing to rename here".to_string()),
+    };
+
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    let mut edit = |document: &Expression, start, end, new_text: String| {
+        changes
+            .entry(document.uri.clone())
+            .or_default()
+            .push(TextEdit {
+                range: utils::offset_range_to_range(&document.document.text, start, end),
+                new_text,
+            })
+    };
+    match target {
+        Target::Local(analysis, definition) => {
+            if utils::KEYWORDS.contains(&new_name) {
+                return Err(format!("`{}` is a keyword", new_name));
+            }
+            if !utils::is_identifier(new_name) {
+                return Err(format!("`{}` is not a valid identifier", new_name));
+            }
+            let definition_span = &analysis.definitions[definition];
+            let spans: Vec<(usize, usize)> =
+                std::iter::once((definition_span.start, definition_span.end))
+                    .chain(
+                        analysis
+                            .references
+                            .iter()
+                            .filter(|reference| reference.definition == Some(definition))
+                            .map(|reference| (reference.start, reference.end)),
+                    )
+                    .collect();
+            if !keeps_bindings(&root, &analysis, &spans, new_name) {
+                return Err(format!(
+                    "`{}` is already used where `{}` is visible",
+                    new_name, definition_span.name
+                ));
+            }
+            for (start, end) in spans {
+                edit(&root, start, end, new_name.to_string());
+            }
+        }
+        Target::Fields(fields) => {
+            // Layers that override each other have to keep the same name.
+            let fields = references::overrides(files, index, &resolver, fields);
+            let identifier = utils::is_identifier(new_name);
+            let quoted = formatter::quote(new_name);
+            for field in &fields {
+                let new_text = if identifier { new_name } else { &quoted };
+                edit(&field.layer, field.start, field.end, new_text.to_string());
+            }
+            for (document, start, end) in references::accesses(files, index, &resolver, &fields) {
+                let text = &document.document.text;
+                if !utils::is_identifier(&text[start..end]) {
+                    // `x['name']`
+                    edit(&document, start, end, quoted.clone());
+                } else if identifier {
+                    edit(&document, start, end, new_name.to_string());
+                } else {
+                    // `x.name` has to become `x['new name']`.
+                    let dot = dot_before(&document, start).unwrap_or(start);
+                    edit(&document, dot, end, format!("[{}]", quoted));
+                }
+            }
+        }
+    }
+    Ok(WorkspaceEdit {
+        changes: Some(changes),
+        ..WorkspaceEdit::default()
+    })
+}
+
+/// Whether every variable in the document still refers to the same
+/// definition after the names at `spans` are replaced by `new_name`, so the
+/// new name neither hides another binding nor is hidden by one.
+fn keeps_bindings(
+    root: &Expression,
+    analysis: &Analysis,
+    spans: &[(usize, usize)],
+    new_name: &str,
+) -> bool {
+    let text = &root.document.text;
+    let mut spans = spans.to_vec();
+    spans.sort_unstable();
+    let mut renamed = String::new();
+    let mut last = 0;
+    for &(start, end) in &spans {
+        renamed.push_str(&text[last..start]);
+        renamed.push_str(new_name);
+        last = end;
+    }
+    renamed.push_str(&text[last..]);
+    // Where an offset before the rename ends up after it.
+    let shift = |offset: usize| {
+        spans
+            .iter()
+            .filter(|(_, end)| *end <= offset)
+            .fold(offset, |offset, (start, end)| {
+                offset + new_name.len() - (end - start)
+            })
+    };
+    let after = scope::analyze(&Document::new(&root.uri, renamed));
+    let same_references = analysis.references.len() == after.references.len()
+        && analysis
+            .references
+            .iter()
+            .zip(&after.references)
+            .all(|(before, renamed)| {
+                before
+                    .definition
+                    .map(|definition| shift(analysis.definitions[definition].start))
+                    == renamed
+                        .definition
+                        .map(|definition| after.definitions[definition].start)
+            });
+    let same_duplicates = analysis
+        .definitions
+        .iter()
+        .zip(&after.definitions)
+        .all(|(before, renamed)| before.duplicate == renamed.duplicate);
+    same_references && same_duplicates
+}
+
+/// The start of the `.` in front of the field name at `offset`.
+fn dot_before(document: &Expression, offset: usize) -> Option<usize> {
+    let tokens = &document.document.cst.tokens;
+    let index = tokens.partition_point(|t| t.start < offset);
+    tokens[..index]
+        .iter()
+        .rev()
+        .find(|t| !t.kind.is_trivia())
+        .filter(|t| t.kind == TokenKind::Symbol && t.text(&document.document.text) == ".")
+        .map(|t| t.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::{TextEdit, Url};
+
+    use crate::{document::Document, files::Files, testing::TempDir, utils, workspace::Index};
+
+    fn rename(code: &str, at: &str, new_name: &str) -> Result<String, String> {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let position = utils::offset_to_position(code, code.find(at).unwrap());
+        assert!(super::prepare(&files, &uri, position).is_some());
+
+        let edit = super::rename(&files, &Index::new(vec![]), &uri, position, new_name)?;
+        let mut edits: Vec<TextEdit> = edit.changes.unwrap().remove(&uri).unwrap();
+        edits.sort_by_key(|edit| edit.range.start);
+        let mut result = code.to_string();
+        for edit in edits.iter().rev() {
+            let start = utils::position_to_offset(code, edit.range.start);
+            let end = utils::position_to_offset(code, edit.range.end);
+            result.replace_range(start..end, &edit.new_text);
+        }
+        Ok(result)
+    }
+
+    #[test]
+    fn locals() {
+        let code = "local a = 1; local f(a) = a; [a, f(a)]";
+        assert_eq!(
+            rename(code, "a = 1", "b").unwrap(),
+            "local b = 1; local f(a) = a; [b, f(b)]"
+        );
+        assert_eq!(
+            rename(code, "a) =", "x").unwrap(),
+            "local a = 1; local f(x) = x; [a, f(a)]"
+        );
+        assert!(rename(code, "a = 1", "local").is_err());
+        assert!(rename(code, "a = 1", "not valid").is_err());
+    }
+
+    #[test]
+    fn locals_keep_their_bindings() {
+        // `b` would refer to the renamed local.
+        assert!(rename("local b = 1; local a = 2; a + b", "a = 2", "b").is_err());
+        // `a` would refer to the parameter.
+        assert!(rename("local a = 1; local f(b) = a + b; f(2)", "a = 1", "b").is_err());
+        // The outer `b` would be hidden by the object local.
+        assert!(rename("local b = 1; { local a = 2, x: b + a }", "a = 2", "b").is_err());
+        assert!(rename("local a = 1, c = 2; a + c", "a = 1", "c").is_err());
+        assert_eq!(
+            rename("local b = 1; local f(a) = a; f(b)", "a) =", "b").unwrap(),
+            "local b = 1; local f(b) = b; f(b)"
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let code = "local o = { a: 1, b: self.a }; [o.a, o['a']]";
+        assert_eq!(
+            rename(code, "a: 1", "c").unwrap(),
+            "local o = { c: 1, b: self.c }; [o.c, o['c']]"
+        );
+        assert_eq!(
+            rename(code, "a, o[", "new name").unwrap(),
+            "local o = { 'new name': 1, b: self['new name'] }; [o['new name'], o['new name']]"
+        );
+    }
+
+    #[test]
+    fn overridden_fields() {
+        let code = "local base = { f: 1 };\nlocal o = base + { f: 2, g: self.f };\n[o.f, base.f]";
+        let renamed =
+            "local base = { h: 1 };\nlocal o = base + { h: 2, g: self.h };\n[o.h, base.h]";
+        assert_eq!(rename(code, "f: 2", "h").unwrap(), renamed);
+        assert_eq!(rename(code, "f: 1", "h").unwrap(), renamed);
+    }
+
+    #[test]
+    fn fields_in_importers_on_disk() {
+        let root = TempDir::new("rename");
+        let lib_code = "{ a: 1 }";
+        fs::write(root.join("lib.libsonnet"), lib_code).unwrap();
+        fs::write(
+            root.join("main.jsonnet"),
+            "local lib = import 'lib.libsonnet';\nlib.a",
+        )
+        .unwrap();
+        let lib = Url::from_file_path(root.join("lib.libsonnet")).unwrap();
+        let main = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+
+        // Only the library is open, the file using it is not.
+        let mut files = Files::new(vec![]);
+        files.insert(lib.clone(), Document::new(&lib, lib_code.to_string()));
+        let index = Index::new(vec![root.to_path_buf()]);
+        let position = utils::offset_to_position(lib_code, lib_code.find('a').unwrap());
+        let mut changes = super::rename(&files, &index, &lib, position, "b")
+            .unwrap()
+            .changes
+            .unwrap();
+        let edits = |edits: Vec<TextEdit>| -> Vec<(u32, u32, String)> {
+            edits
+                .into_iter()
+                .map(|edit| {
+                    (
+                        edit.range.start.line,
+                        edit.range.start.character,
+                        edit.new_text,
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(
+            edits(changes.remove(&lib).unwrap()),
+            [(0, 2, "b".to_string())]
+        );
+        assert_eq!(
+            edits(changes.remove(&main).unwrap()),
+            [(1, 4, "b".to_string())]
+        );
+        assert!(changes.is_empty());
+    }
+}
@@ -0,0 +1,584 @@
+//! Name resolution for locals, function parameters and comprehension
+//! variables.
+//!
+//! The AST only knows where expressions are, not where the names that bind
+//! variables are written, so those are looked up in the token stream
+//! following the expression that precedes them.
+
+use jrsonnet_parser::{
+    BindSpec, CompSpec, Expr, FieldMember, FieldName, LocExpr, Member, ObjBody, ParamsDesc,
+};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Local,
+    Parameter,
+    ForVariable,
+}
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    pub kind: Kind,
+    /// Byte range of the name where it is bound.
+    pub start: usize,
+    pub end: usize,
+    /// The expression bound to a local, which is the body if the local is a
+    /// function. `None` for parameters and `for` variables.
+    pub value: Option<LocExpr>,
+    pub params: Option<ParamsDesc>,
+    /// The definition of the same name in an enclosing scope that this one
+    /// hides.
+    pub shadows: Option<usize>,
+    /// Whether a name bound before this one in the same group, the names of
+    /// a `local` chain, a parameter list or the locals of an object, is the
+    /// same.
+    pub duplicate: bool,
+    /// For locals, their position among the locals written in the same
+    /// `local`, and how many there are. Object locals each have their own.
+    pub chain: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub start: usize,
+    pub end: usize,
+    /// Index into [`Analysis::definitions`], `None` for names that are not
+    /// bound in the document, like `std`.
+    pub definition: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    /// The definitions in scope at the offset given to [`analyze_at`],
+    /// innermost last.
+    pub visible: Vec<usize>,
+    /// The references to names that are not bound in the document, as an
+    /// index into [`Analysis::references`], with the definitions in scope
+    /// there, innermost last.
+    pub unbound: Vec<(usize, Vec<usize>)>,
+}
+
+impl Analysis {
+    /// The definition of the name at `offset`, which is either a use of a
+    /// variable or the place where it is bound.
+    pub fn definition_at(&self, offset: usize) -> Option<usize> {
+        let contains = |start: usize, end: usize| start <= offset && offset <= end;
+        if let Some(reference) = self.references.iter().find(|r| contains(r.start, r.end)) {
+            return reference.definition;
+        }
+        self.definitions
+            .iter()
+            .position(|d| contains(d.start, d.end))
+    }
+}
+
+pub fn start(expr: &LocExpr) -> usize {
+    expr.1.as_ref().map_or(0, |l| l.1)
+}
+
+pub fn end(expr: &LocExpr) -> usize {
+    expr.1.as_ref().map_or(0, |l| l.2)
+}
+
+pub fn member_end(member: &Member) -> usize {
+    match member {
+        Member::Field(field) => end(&field.value),
+        Member::BindStmt(bind) => end(&bind.value),
+        Member::AssertStmt(assert) => end(assert.1.as_ref().unwrap_or(&assert.0)),
+    }
+}
+
+pub fn analyze(document: &Document) -> Analysis {
+    walk(document, None)
+}
+
+/// Like [`analyze`], but also collects the definitions that are in scope at
+/// `offset`.
+pub fn analyze_at(document: &Document, offset: usize) -> Analysis {
+    walk(document, Some(offset))
+}
+
+fn walk(document: &Document, cursor: Option<usize>) -> Analysis {
+    let mut walker = Walker {
+        code: &document.text,
+        tree: &document.cst,
+        analysis: Analysis::default(),
+        env: vec![],
+        group: 0,
+        cursor,
+    };
+    walker.expr(&document.ast);
+    walker.analysis
+}
+
+struct Walker<'a> {
+    code: &'a str,
+    tree: &'a cst::Tree,
+    analysis: Analysis,
+    /// Names in scope, innermost last.
+    env: Vec<(String, usize)>,
+    /// Where the names bound together with the next one start in `env`.
+    group: usize,
+    cursor: Option<usize>,
+}
+
+impl<'a> Walker<'a> {
+    /// Finds `name` as the first identifier at or after `offset`.
+    fn find_name(&self, offset: usize, name: &str) -> Option<(usize, usize)> {
+        let first = self.tree.tokens.partition_point(|t| t.start < offset);
+        let token = self.tree.tokens[first..]
+            .iter()
+            .find(|t| t.kind == TokenKind::Ident)?;
+        if token.text(self.code) == name {
+            Some((token.start, token.end))
+        } else {
+            None
+        }
+    }
+
+    /// The end of the first `symbol` at or after `offset`.
+    fn after_symbol(&self, offset: usize, symbol: &str) -> usize {
+        let first = self.tree.tokens.partition_point(|t| t.start < offset);
+        self.tree.tokens[first..]
+            .iter()
+            .find(|t| t.kind == TokenKind::Symbol && t.text(self.code) == symbol)
+            .map_or(offset, |t| t.end)
+    }
+
+    /// Brings `name` into scope if it can be found at or after `offset`.
+    /// Returns the end of the name.
+    fn define(
+        &mut self,
+        offset: usize,
+        name: &str,
+        kind: Kind,
+        bind: Option<&BindSpec>,
+        chain: Option<(usize, usize)>,
+    ) -> Option<usize> {
+        let (start, end) = self.find_name(offset, name)?;
+        let (outer, group) = self.env.split_at(self.group);
+        let shadows = outer
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, definition)| *definition);
+        let duplicate = group.iter().any(|(bound, _)| bound == name);
+        self.env
+            .push((name.to_string(), self.analysis.definitions.len()));
+        self.analysis.definitions.push(Definition {
+            name: name.to_string(),
+            kind,
+            start,
+            end,
+            value: bind.map(|bind| bind.value.clone()),
+            params: bind.and_then(|bind| bind.params.clone()),
+            shadows,
+            duplicate,
+            chain,
+        });
+        Some(end)
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.env
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, definition)| *definition)
+    }
+
+    /// Defines the names of a `local` statement, which are all visible in
+    /// each other's values. Returns the end of each name. Unless `chained`,
+    /// each local is written on its own, like object locals.
+    fn define_binds(&mut self, offset: usize, binds: &[BindSpec], chained: bool) -> Vec<usize> {
+        self.group = self.env.len();
+        self.define_more_binds(offset, binds, chained)
+    }
+
+    /// Like [`Walker::define_binds`], but the names join the group of the
+    /// names defined last.
+    fn define_more_binds(
+        &mut self,
+        mut offset: usize,
+        binds: &[BindSpec],
+        chained: bool,
+    ) -> Vec<usize> {
+        let mut ends = vec![];
+        for (index, bind) in binds.iter().enumerate() {
+            let chain = if chained {
+                (index, binds.len())
+            } else {
+                (0, 1)
+            };
+            let name_end = self.define(offset, &bind.name, Kind::Local, Some(bind), Some(chain));
+            ends.push(name_end.unwrap_or(offset));
+            offset = end(&bind.value);
+        }
+        ends
+    }
+
+    fn bind_value(&mut self, name_end: usize, bind: &BindSpec) {
+        self.function(name_end, bind.params.as_ref(), &bind.value);
+    }
+
+    /// Walks a function body with its parameters, which are written after
+    /// `offset`, in scope.
+    fn function(&mut self, mut offset: usize, params: Option<&ParamsDesc>, body: &LocExpr) {
+        let mark = self.env.len();
+        self.group = mark;
+        if let Some(params) = params {
+            for param in params.iter() {
+                offset = self
+                    .define(offset, &param.0, Kind::Parameter, None, None)
+                    .unwrap_or(offset);
+                if let Some(default) = &param.1 {
+                    offset = end(default);
+                }
+            }
+            for param in params.iter() {
+                if let Some(default) = &param.1 {
+                    self.expr(default);
+                }
+            }
+        }
+        self.expr(body);
+        self.env.truncate(mark);
+    }
+
+    /// Walks comprehension specs, defining the `for` variables, and then
+    /// calls `inner` with all of them in scope.
+    fn comprehension(
+        &mut self,
+        mut offset: usize,
+        specs: &[CompSpec],
+        inner: impl FnOnce(&mut Self),
+    ) {
+        let mark = self.env.len();
+        for spec in specs {
+            match spec {
+                CompSpec::ForSpec(for_spec) => {
+                    // The variable is not visible in its own source.
+                    self.expr(&for_spec.1);
+                    self.group = self.env.len();
+                    self.define(offset, &for_spec.0, Kind::ForVariable, None, None);
+                    offset = end(&for_spec.1);
+                }
+                CompSpec::IfSpec(if_spec) => {
+                    self.expr(&if_spec.0);
+                    offset = end(&if_spec.0);
+                }
+            }
+        }
+        inner(self);
+        self.env.truncate(mark);
+    }
+
+    fn object(&mut self, offset: usize, body: &ObjBody) {
+        match body {
+            ObjBody::MemberList(members) => {
+                // Field names are evaluated outside the object, without its
+                // locals.
+                for member in members {
+                    if let Member::Field(FieldMember {
+                        name: FieldName::Dyn(name),
+                        ..
+                    }) = member
+                    {
+                        self.expr(name);
+                    }
+                }
+                let mark = self.env.len();
+                self.group = mark;
+                let mut member_start = offset;
+                let mut name_ends = vec![];
+                for member in members {
+                    if let Member::BindStmt(bind) = member {
+                        name_ends.push(self.define(
+                            member_start,
+                            &bind.name,
+                            Kind::Local,
+                            Some(bind),
+                            Some((0, 1)),
+                        ));
+                    }
+                    member_start = member_end(member);
+                }
+                let mut name_ends = name_ends.into_iter();
+                let mut member_start = offset;
+                for member in members {
+                    match member {
+                        Member::Field(field) => {
+                            let mut params_start = member_start;
+                            if let FieldName::Dyn(name) = &field.name {
+                                params_start = end(name);
+                            }
+                            if field.params.is_some() {
+                                // Skip the field name, which may be an
+                                // identifier itself.
+                                params_start = self.after_symbol(params_start, "(");
+                            }
+                            self.function(params_start, field.params.as_ref(), &field.value);
+                        }
+                        Member::BindStmt(bind) => {
+                            let name_end = name_ends.next().flatten().unwrap_or(member_start);
+                            self.bind_value(name_end, bind);
+                        }
+                        Member::AssertStmt(assert) => {
+                            self.expr(&assert.0);
+                            if let Some(message) = &assert.1 {
+                                self.expr(message);
+                            }
+                        }
+                    }
+                    member_start = member_end(member);
+                }
+                self.env.truncate(mark);
+            }
+            ObjBody::ObjComp(comp) => {
+                let specs_start = comp
+                    .post_locals
+                    .last()
+                    .map_or_else(|| end(&comp.value), |bind| end(&bind.value));
+                self.comprehension(specs_start, &comp.compspecs, |walker| {
+                    walker.expr(&comp.key);
+                    let mark = walker.env.len();
+                    let pre_ends = walker.define_binds(offset, &comp.pre_locals, false);
+                    // The locals after the field are bound together with the
+                    // ones before it.
+                    let post_ends =
+                        walker.define_more_binds(end(&comp.value), &comp.post_locals, false);
+                    for (bind, name_end) in comp.pre_locals.iter().zip(pre_ends) {
+                        walker.bind_value(name_end, bind);
+                    }
+                    walker.expr(&comp.value);
+                    for (bind, name_end) in comp.post_locals.iter().zip(post_ends) {
+                        walker.bind_value(name_end, bind);
+                    }
+                    walker.env.truncate(mark);
+                });
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &LocExpr) {
+        if let (Some(cursor), Some(location)) = (self.cursor, &expr.1) {
+            if location.1 <= cursor && cursor <= location.2 {
+                self.analysis.visible =
+                    self.env.iter().map(|(_, definition)| *definition).collect();
+            }
+        }
+        match &*expr.0 {
+            // Empty names mark expressions the parser could not make sense of.
+            Expr::Var(name) if name.is_empty() => {}
+            Expr::Var(name) => {
+                if let Some(location) = &expr.1 {
+                    let definition = self.lookup(name);
+                    if definition.is_none() {
+                        let in_scope = self.env.iter().map(|(_, definition)| *definition);
+                        self.analysis
+                            .unbound
+                            .push((self.analysis.references.len(), in_scope.collect()));
+                    }
+                    self.analysis.references.push(Reference {
+                        start: location.1,
+                        end: location.2,
+                        definition,
+                    });
+                }
+            }
+            Expr::LocalExpr(binds, body) => {
+                let mark = self.env.len();
+                let name_ends = self.define_binds(start(expr), binds, true);
+                for (bind, name_end) in binds.iter().zip(name_ends) {
+                    self.bind_value(name_end, bind);
+                }
+                self.expr(body);
+                self.env.truncate(mark);
+            }
+            Expr::Function(params, body) => self.function(start(expr), Some(params), body),
+            Expr::ArrComp(value, specs) => {
+                self.comprehension(end(value), specs, |walker| walker.expr(value))
+            }
+            Expr::Obj(body) => self.object(start(expr), body),
+            Expr::ObjExtend(base, body) => {
+                self.expr(base);
+                self.object(end(base), body);
+            }
+            _ => {
+                for child in cst::children(expr) {
+                    self.expr(child);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Url;
+
+    use crate::document::Document;
+
+    /// Resolves the `n`th occurrence of `name` in `code` and returns the
+    /// occurrence number of the definition it resolves to.
+    fn resolve(code: &str, name: &str, n: usize) -> Option<usize> {
+        let document = Document::new(
+            &Url::parse("file:///test.jsonnet").unwrap(),
+            code.to_string(),
+        );
+        let analysis = super::analyze(&document);
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let offsets: Vec<_> = code
+            .match_indices(name)
+            .map(|(i, _)| i)
+            .filter(|&i| {
+                !code[..i].ends_with(is_ident) && !code[i + name.len()..].starts_with(is_ident)
+            })
+            .collect();
+        let definition = analysis.definition_at(offsets[n])?;
+        let start = analysis.definitions[definition].start;
+        offsets.iter().position(|offset| *offset == start)
+    }
+
+    #[test]
+    fn locals_and_shadowing() {
+        let code = "local a = 1; local b = a; local a = b + a; [a, b]";
+        assert_eq!(resolve(code, "a", 1), Some(0));
+        // A local is visible in its own value, hiding the one it shadows.
+        assert_eq!(resolve(code, "a", 3), Some(2));
+        assert_eq!(resolve(code, "a", 4), Some(2));
+        assert_eq!(resolve(code, "b", 2), Some(0));
+    }
+
+    #[test]
+    fn mutually_recursive_binds() {
+        let code = "local f(n) = g(n), g(m) = f(m); f(1)";
+        assert_eq!(resolve(code, "g", 0), Some(1));
+        assert_eq!(resolve(code, "f", 1), Some(0));
+        assert_eq!(resolve(code, "n", 1), Some(0));
+        assert_eq!(resolve(code, "m", 1), Some(0));
+    }
+
+    #[test]
+    fn params_and_comprehensions() {
+        let code = "function(x, y=x) [x + z for x in y for z in [x]]";
+        assert_eq!(resolve(code, "x", 1), Some(0));
+        assert_eq!(resolve(code, "x", 2), Some(3));
+        assert_eq!(resolve(code, "y", 1), Some(0));
+        assert_eq!(resolve(code, "x", 4), Some(3));
+        assert_eq!(resolve(code, "z", 0), Some(1));
+    }
+
+    #[test]
+    fn object_locals() {
+        let code = r#"
+local v = 1;
+{
+  local v = 2,
+  local w = v,
+  a: v + w,
+  f(v):: v,
+  b: { local w = 3, c: w },
+}"#;
+        assert_eq!(resolve(code, "v", 2), Some(1));
+        assert_eq!(resolve(code, "v", 3), Some(1));
+        assert_eq!(resolve(code, "w", 1), Some(0));
+        assert_eq!(resolve(code, "v", 5), Some(4));
+        assert_eq!(resolve(code, "w", 3), Some(2));
+    }
+
+    #[test]
+    fn field_names_outside_object_locals() {
+        let code = "local x = 'a';\n{ local x = 'b', [x]: x }";
+        assert_eq!(resolve(code, "x", 2), Some(0));
+        assert_eq!(resolve(code, "x", 3), Some(1));
+        assert_eq!(resolve("{ local x = 'a', [x]: 1 }", "x", 1), None);
+        let code = "{ local x = k, [x + k]: x for k in ['a'] }";
+        assert_eq!(resolve(code, "x", 1), None);
+        assert_eq!(resolve(code, "x", 2), Some(0));
+    }
+
+    #[test]
+    fn shadows_and_duplicates() {
+        let code =
+            "local a = 1; function(a, b, b) { local c = a, local a = c, d: [a for a in []] }";
+        let document = Document::new(
+            &Url::parse("file:///test.jsonnet").unwrap(),
+            code.to_string(),
+        );
+        let definitions: Vec<_> = super::analyze(&document)
+            .definitions
+            .into_iter()
+            .map(|d| (d.name, d.shadows, d.duplicate))
+            .collect();
+        let name = |name: &str| name.to_string();
+        assert_eq!(
+            definitions,
+            [
+                (name("a"), None, false),
+                (name("a"), Some(0), false),
+                (name("b"), None, false),
+                (name("b"), None, true),
+                (name("c"), None, false),
+                (name("a"), Some(1), false),
+                (name("a"), Some(5), false),
+            ]
+        );
+
+        // The locals of an object comprehension around its field.
+        let code = "{ local x = 1, [k]: x, local x = 2, local y = x for k in ['a'] }";
+        let document = Document::new(
+            &Url::parse("file:///test.jsonnet").unwrap(),
+            code.to_string(),
+        );
+        let duplicates: Vec<_> = super::analyze(&document)
+            .definitions
+            .into_iter()
+            .map(|d| (d.name, d.duplicate))
+            .collect();
+        assert_eq!(
+            duplicates,
+            [
+                (name("k"), false),
+                (name("x"), false),
+                (name("x"), true),
+                (name("y"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn unbound_names() {
+        let code = "std.length(x)";
+        assert_eq!(resolve(code, "std", 0), None);
+        assert_eq!(resolve(code, "x", 0), None);
+
+        // What is in scope where each unbound name is used.
+        let code = "local a = 1; [std, function(b) c]";
+        let document = Document::new(
+            &Url::parse("file:///test.jsonnet").unwrap(),
+            code.to_string(),
+        );
+        let analysis = super::analyze(&document);
+        let unbound: Vec<(&str, Vec<&str>)> = analysis
+            .unbound
+            .iter()
+            .map(|(reference, in_scope)| {
+                let reference = &analysis.references[*reference];
+                let names = in_scope
+                    .iter()
+                    .map(|&definition| analysis.definitions[definition].name.as_str());
+                (&code[reference.start..reference.end], names.collect())
+            })
+            .collect();
+        assert_eq!(unbound, [("std", vec!["a"]), ("c", vec!["a", "b"])]);
+    }
+}
@@ -0,0 +1,146 @@
+//! Selection ranges, which expand from the token at the cursor through the
+//! expressions containing it, the fields and locals they are the value of,
+//! up to the whole document.
+
+use lsp_types::{Position, SelectionRange, Url};
+
+use crate::{
+    cst,
+    fields::{Expression, Object},
+    files::Files,
+    scope::{self, Kind},
+    utils,
+};
+
+pub fn ranges(files: &Files, uri: &Url, positions: Vec<Position>) -> Vec<SelectionRange> {
+    let document = match files.get(uri) {
+        Some(document) => document,
+        None => return vec![],
+    };
+    let root = Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document: document.clone(),
+    };
+    let text = &document.text;
+    positions
+        .into_iter()
+        .map(|position| {
+            let offset = utils::position_to_offset(text, position);
+            let mut selection = None;
+            for (start, end) in spans(&root, offset).into_iter().rev() {
+                selection = Some(SelectionRange {
+                    range: utils::offset_range_to_range(text, start, end),
+                    parent: selection.map(Box::new),
+                });
+            }
+            selection.unwrap_or(SelectionRange {
+                range: utils::offset_range_to_range(text, offset, offset),
+                parent: None,
+            })
+        })
+        .collect()
+}
+
+/// The byte ranges containing `offset`, innermost first, each containing
+/// the previous one.
+fn spans(root: &Expression, offset: usize) -> Vec<(usize, usize)> {
+    let document = &root.document;
+    let contains = |start: usize, end: usize| start <= offset && offset <= end;
+    let mut spans = vec![];
+
+    if let Some(token) = document
+        .cst
+        .tokens
+        .iter()
+        .filter(|t| !t.kind.is_trivia())
+        .find(|t| contains(t.start, t.end))
+    {
+        spans.push((token.start, token.end));
+    }
+
+    let path = cst::path_at(&root.expr, offset);
+    for expr in &path {
+        spans.push((scope::start(expr), scope::end(expr)));
+        let object = Object {
+            layers: vec![root.with((*expr).clone())],
+        };
+        for field in object.fields() {
+            let end = scope::end(&field.member().value);
+            if contains(field.start, end) {
+                spans.push((field.start, end));
+            }
+        }
+    }
+
+    // A local from its name to the end of its value.
+    for definition in &scope::analyze(document).definitions {
+        if let (Kind::Local, Some(value)) = (definition.kind, &definition.value) {
+            if contains(definition.start, scope::end(value)) {
+                spans.push((definition.start, scope::end(value)));
+            }
+        }
+    }
+
+    spans.push((0, document.text.len()));
+    spans.sort_by_key(|(start, end)| end - start);
+    let mut nested: Vec<(usize, usize)> = vec![];
+    for (start, end) in spans {
+        let encloses = match nested.last() {
+            Some(&inner) => start <= inner.0 && inner.1 <= end && (start, end) != inner,
+            None => true,
+        };
+        if encloses {
+            nested.push((start, end));
+        }
+    }
+    nested
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Url;
+
+    use crate::{document::Document, files::Files, utils};
+
+    /// The text of each selection range at `|` in `code`, innermost first.
+    fn expand(code: &str) -> Vec<String> {
+        let offset = code.find('|').unwrap();
+        let code = code.replacen('|', "", 1);
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.clone()));
+        let position = utils::offset_to_position(&code, offset);
+        let mut selection = super::ranges(&files, &uri, vec![position]).pop();
+        let mut texts = vec![];
+        while let Some(range) = selection {
+            let start = utils::position_to_offset(&code, range.range.start);
+            let end = utils::position_to_offset(&code, range.range.end);
+            texts.push(code[start..end].to_string());
+            selection = range.parent.map(|parent| *parent);
+        }
+        texts
+    }
+
+    #[test]
+    fn expands_through_the_ast() {
+        let code =
+            "local lib = import 'lib.libsonnet';\nlocal app = {\n  spec: lib.new(na|me),\n};\napp";
+        assert_eq!(
+            expand(code),
+            [
+                "name",
+                "lib.new(name)",
+                "spec: lib.new(name)",
+                "{\n  spec: lib.new(name),\n}",
+                "app = {\n  spec: lib.new(name),\n}",
+                "local app = {\n  spec: lib.new(name),\n};\napp",
+                &code.replacen('|', "", 1)[..],
+            ]
+        );
+        assert_eq!(
+            expand("{ a: { b: x.fi|eld + 1 } }")[..4],
+            ["field", "x.field", "x.field + 1", "b: x.field + 1"]
+        );
+    }
+}
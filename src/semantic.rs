@@ -0,0 +1,386 @@
+//! Semantic tokens, which tell locals, parameters and fields apart where a
+//! grammar only sees identifiers.
+
+use jrsonnet_parser::{Arg, BinaryOpType, Expr, LocExpr, ObjBody, Visibility};
+use lsp_types::{
+    Range, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens,
+    SemanticTokensLegend, Url,
+};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+    fields::{Expression, Object, Resolver},
+    files::Files,
+    scope::{self, Analysis, Kind},
+    stdlib, utils,
+};
+
+/// The token types in the order of [`legend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Variable,
+    Parameter,
+    Property,
+    Function,
+    Namespace,
+    Keyword,
+    FormatSpecifier,
+}
+
+/// The token modifiers as bits, in the order of [`legend`].
+const DECLARATION: u32 = 1;
+const DEFAULT_LIBRARY: u32 = 1 << 1;
+const HIDDEN: u32 = 1 << 2;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: vec![
+            SemanticTokenType::VARIABLE,
+            SemanticTokenType::PARAMETER,
+            SemanticTokenType::PROPERTY,
+            SemanticTokenType::FUNCTION,
+            SemanticTokenType::NAMESPACE,
+            SemanticTokenType::KEYWORD,
+            SemanticTokenType::new("formatSpecifier"),
+        ],
+        token_modifiers: vec![
+            SemanticTokenModifier::DECLARATION,
+            SemanticTokenModifier::DEFAULT_LIBRARY,
+            SemanticTokenModifier::new("hidden"),
+        ],
+    }
+}
+
+struct Highlight {
+    start: usize,
+    end: usize,
+    kind: Type,
+    modifiers: u32,
+}
+
+/// The semantic tokens of the document, or of the part of it in `range`.
+pub fn tokens(files: &Files, uri: &Url, range: Option<Range>) -> Option<SemanticTokens> {
+    let document = files.get(uri)?;
+    let text = &document.text;
+    let root = Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document: document.clone(),
+    };
+    let mut highlights = highlights(files, &root);
+    if let Some(range) = range {
+        let start = utils::position_to_offset(text, range.start);
+        let end = utils::position_to_offset(text, range.end);
+        highlights.retain(|h| h.start < end && start < h.end);
+    }
+    Some(SemanticTokens {
+        result_id: None,
+        data: encode(text, &highlights),
+    })
+}
+
+/// The highlights in the document, ordered and without overlaps.
+fn highlights(files: &Files, root: &Expression) -> Vec<Highlight> {
+    let document = &root.document;
+    let text = &document.text;
+    let analysis = scope::analyze(document);
+    let resolver = Resolver::new(files);
+    let mut highlights = vec![];
+    let mut push = |start: usize, end: usize, kind: Type, modifiers: u32| {
+        highlights.push(Highlight {
+            start,
+            end,
+            kind,
+            modifiers,
+        })
+    };
+
+    let variable = |kind: Kind, function: bool| match kind {
+        Kind::Local if function => Type::Function,
+        Kind::Local | Kind::ForVariable => Type::Variable,
+        Kind::Parameter => Type::Parameter,
+    };
+    for definition in &analysis.definitions {
+        let kind = variable(definition.kind, definition.params.is_some());
+        push(definition.start, definition.end, kind, DECLARATION);
+    }
+    for reference in &analysis.references {
+        match reference.definition {
+            Some(index) => {
+                let definition = &analysis.definitions[index];
+                let kind = variable(definition.kind, definition.params.is_some());
+                push(reference.start, reference.end, kind, 0);
+            }
+            None if &text[reference.start..reference.end] == "std" => push(
+                reference.start,
+                reference.end,
+                Type::Namespace,
+                DEFAULT_LIBRARY,
+            ),
+            None => {}
+        }
+    }
+
+    for expr in cst::descendants(&root.expr) {
+        match &*expr.0 {
+            Expr::Index(target, index) => {
+                let name = match &*index.0 {
+                    Expr::Str(name) => name,
+                    _ => continue,
+                };
+                let (start, end) = (scope::start(index), scope::end(index));
+                // Only `target.name`, `target['name']` is a string.
+                if !utils::is_identifier(&text[start..end]) {
+                    continue;
+                }
+                let function = stdlib::find(name).filter(|_| is_std(&analysis, target));
+                if let Some(function) = function {
+                    let kind = match function.params {
+                        Some(_) => Type::Function,
+                        None => Type::Property,
+                    };
+                    push(start, end, kind, DEFAULT_LIBRARY);
+                } else {
+                    let hidden = resolver
+                        .object(&root.with(target.clone()))
+                        .is_some_and(|object| object.hidden(name));
+                    push(start, end, Type::Property, if hidden { HIDDEN } else { 0 });
+                }
+            }
+            Expr::Obj(ObjBody::MemberList(_)) | Expr::ObjExtend(_, ObjBody::MemberList(_)) => {
+                let object = Object {
+                    layers: vec![root.with(expr.clone())],
+                };
+                for field in object.fields() {
+                    let hidden = field.member().visibility == Visibility::Hidden;
+                    let modifiers = DECLARATION | if hidden { HIDDEN } else { 0 };
+                    push(field.start, field.end, Type::Property, modifiers);
+                }
+            }
+            Expr::BinaryOp(format, BinaryOpType::Mod, _) => {
+                for (start, end) in placeholders(document, format) {
+                    push(start, end, Type::FormatSpecifier, 0);
+                }
+            }
+            Expr::Apply(callee, args, _) => {
+                let format = match (&*callee.0, args.0.first()) {
+                    (Expr::Index(target, name), Some(Arg(None, format)))
+                        if is_std(&analysis, target) =>
+                    {
+                        match &*name.0 {
+                            Expr::Str(name) if &**name == "format" => format,
+                            _ => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                for (start, end) in placeholders(document, format) {
+                    push(start, end, Type::FormatSpecifier, 0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for token in &document.cst.tokens {
+        if matches!(
+            (token.kind, token.text(text)),
+            (TokenKind::Keyword, "self" | "super") | (TokenKind::Symbol, "$")
+        ) {
+            push(token.start, token.end, Type::Keyword, 0);
+        }
+    }
+
+    // Earlier highlights win where they overlap, like a `std` function over
+    // a field access.
+    highlights.sort_by_key(|h| h.start);
+    highlights.dedup_by(|next, kept| next.start < kept.end);
+    highlights
+}
+
+/// Whether `expr` is the `std` object, not a local that shadows it.
+fn is_std(analysis: &Analysis, expr: &LocExpr) -> bool {
+    match &*expr.0 {
+        Expr::Var(name) if &**name == "std" => !analysis
+            .references
+            .iter()
+            .any(|r| r.start == scope::start(expr) && r.definition.is_some()),
+        _ => false,
+    }
+}
+
+/// The byte ranges of the `%` placeholders in the string literal `expr`,
+/// like `%s`, `%05.2f`, `%(name)d` or `%%`.
+fn placeholders(document: &Document, expr: &LocExpr) -> Vec<(usize, usize)> {
+    if !matches!(&*expr.0, Expr::Str(_)) {
+        return vec![];
+    }
+    let start = scope::start(expr);
+    let token = match document
+        .cst
+        .tokens
+        .iter()
+        .find(|t| t.start == start && t.kind == TokenKind::String)
+    {
+        Some(token) => token,
+        None => return vec![],
+    };
+    let bytes = token.text(&document.text).as_bytes();
+    let mut placeholders = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            i += 1;
+            continue;
+        }
+        let mut j = i + 1;
+        if bytes.get(j) == Some(&b'(') {
+            match bytes[j..].iter().position(|&b| b == b')' || b == b'\n') {
+                Some(close) if bytes[j + close] == b')' => j += close + 1,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+        }
+        let skip = |j: &mut usize, allowed: &dyn Fn(u8) -> bool| {
+            while bytes.get(*j).is_some_and(|&b| allowed(b)) {
+                *j += 1;
+            }
+        };
+        skip(&mut j, &|b| b"#0- +".contains(&b));
+        skip(&mut j, &|b| b.is_ascii_digit() || b == b'*');
+        if bytes.get(j) == Some(&b'.') {
+            j += 1;
+            skip(&mut j, &|b| b.is_ascii_digit() || b == b'*');
+        }
+        skip(&mut j, &|b| b"hlL".contains(&b));
+        match bytes.get(j) {
+            Some(b) if b"diouxXeEfFgGcrs%".contains(b) => {
+                placeholders.push((token.start + i, token.start + j + 1));
+                i = j + 1;
+            }
+            _ => i += 1,
+        }
+    }
+    placeholders
+}
+
+/// Encodes the highlights relative to each other, in UTF-16 code units.
+/// Highlights spanning lines are left out, not every client supports them.
+fn encode(text: &str, highlights: &[Highlight]) -> Vec<SemanticToken> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let utf16_len = |s: &str| s.encode_utf16().count() as u32;
+    let mut data = vec![];
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for highlight in highlights {
+        let span = &text[highlight.start..highlight.end];
+        if span.is_empty() || span.contains('\n') {
+            continue;
+        }
+        let line = line_starts.partition_point(|&start| start <= highlight.start) - 1;
+        let start = utf16_len(&text[line_starts[line]..highlight.start]);
+        let line = line as u32;
+        data.push(SemanticToken {
+            delta_line: line - prev_line,
+            delta_start: if line == prev_line {
+                start - prev_start
+            } else {
+                start
+            },
+            length: utf16_len(span),
+            token_type: highlight.kind as u32,
+            token_modifiers_bitset: highlight.modifiers,
+        });
+        prev_line = line;
+        prev_start = start;
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range, Url};
+
+    use crate::{document::Document, files::Files, utils};
+
+    /// The text of each token with its type and modifiers.
+    fn tokens(code: &str, range: Option<Range>) -> Vec<String> {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let legend = super::legend();
+        let mut position = Position::new(0, 0);
+        let mut out = vec![];
+        for token in super::tokens(&files, &uri, range).unwrap().data {
+            position = if token.delta_line == 0 {
+                Position::new(position.line, position.character + token.delta_start)
+            } else {
+                Position::new(position.line + token.delta_line, token.delta_start)
+            };
+            let start = utils::position_to_offset(code, position);
+            let end = utils::position_to_offset(
+                code,
+                Position::new(position.line, position.character + token.length),
+            );
+            let mut line = format!(
+                "{} {}",
+                &code[start..end],
+                legend.token_types[token.token_type as usize].as_str()
+            );
+            for (i, modifier) in legend.token_modifiers.iter().enumerate() {
+                if token.token_modifiers_bitset & (1 << i) != 0 {
+                    line.push(' ');
+                    line.push_str(modifier.as_str());
+                }
+            }
+            out.push(line);
+        }
+        out
+    }
+
+    #[test]
+    fn roles() {
+        let code = r#"local greet(name) = 'Hello %(name)s, %05.1f%%' % { name: name };
+{
+  secret:: 'x',
+  shown: self.secret + $.shown,
+  loud: std.asciiUpper(greet('ü')),
+  msg: std.format('%s!', [super.x]),
+}"#;
+        assert_eq!(
+            tokens(code, None),
+            [
+                "greet function declaration",
+                "name parameter declaration",
+                "%(name)s formatSpecifier",
+                "%05.1f formatSpecifier",
+                "%% formatSpecifier",
+                "name property declaration",
+                "name parameter",
+                "secret property declaration hidden",
+                "shown property declaration",
+                "self keyword",
+                "secret property hidden",
+                "$ keyword",
+                "shown property",
+                "loud property declaration",
+                "std namespace defaultLibrary",
+                "asciiUpper function defaultLibrary",
+                "greet function",
+                "msg property declaration",
+                "std namespace defaultLibrary",
+                "format function defaultLibrary",
+                "%s formatSpecifier",
+                "super keyword",
+                "x property",
+            ]
+        );
+        let range = Range::new(Position::new(3, 0), Position::new(4, 0));
+        assert_eq!(tokens(code, Some(range))[0], "shown property declaration");
+        assert_eq!(tokens(code, Some(range)).len(), 5);
+    }
+}
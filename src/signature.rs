@@ -0,0 +1,271 @@
+//! Signature help for calls of std functions and functions defined in
+//! Jsonnet, like `local f(a, b=1) = ...` or object methods.
+
+use jrsonnet_parser::Expr;
+use lsp_types::{
+    Documentation, MarkupContent, MarkupKind, ParameterInformation, ParameterLabel, Position,
+    SignatureHelp, SignatureInformation, Url,
+};
+
+use crate::{
+    cst::{self, Token, TokenKind},
+    document::Document,
+    fields::{Expression, Resolver},
+    files::Files,
+    scope, stdlib, utils,
+};
+
+/// The call whose arguments contain the cursor.
+struct Call {
+    /// The end of the called expression.
+    callee_end: usize,
+    /// The number of arguments before the one at the cursor.
+    index: usize,
+    /// The name of the argument at the cursor if it is named.
+    named: Option<String>,
+}
+
+pub fn help(files: &Files, uri: &Url, position: Position) -> Option<SignatureHelp> {
+    let document = files.get(uri)?;
+    let offset = utils::position_to_offset(&document.text, position);
+    let root = Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document: document.clone(),
+    };
+    let resolver = Resolver::new(files);
+    for call in calls(&document, offset) {
+        if let Some((mut signature, params)) = signature(&resolver, &root, call.callee_end) {
+            let active = match &call.named {
+                Some(name) => params.iter().position(|param| param == name),
+                None => Some(call.index).filter(|index| *index < params.len()),
+            };
+            signature.active_parameter = active.map(|index| index as u32);
+            return Some(SignatureHelp {
+                signatures: vec![signature],
+                active_signature: Some(0),
+                active_parameter: active.map(|index| index as u32),
+            });
+        }
+    }
+    None
+}
+
+/// The calls around `offset`, innermost first. Every unclosed `(` before
+/// the cursor could be one, whether it is can only be told from the
+/// expression in front of it.
+fn calls(document: &Document, offset: usize) -> Vec<Call> {
+    let text = &document.text;
+    let tokens = &document.cst.tokens;
+    let before: Vec<&Token> = tokens
+        .iter()
+        .take_while(|t| t.end <= offset)
+        .filter(|t| !t.kind.is_trivia())
+        .collect();
+    let mut calls = vec![];
+    let mut depth = 0;
+    let mut index = 0;
+    // Where the argument at the cursor starts, as an index into `before`,
+    // once it is known.
+    let mut argument = None;
+    for (i, token) in before.iter().enumerate().rev() {
+        if token.kind != TokenKind::Symbol {
+            continue;
+        }
+        match token.text(text) {
+            ")" | "]" | "}" => depth += 1,
+            "(" | "[" | "{" if depth > 0 => depth -= 1,
+            "," if depth == 0 => {
+                argument.get_or_insert(i + 1);
+                index += 1;
+            }
+            open @ ("(" | "[" | "{") => {
+                let named = match before[argument.unwrap_or(i + 1)..] {
+                    [name, equals, ..]
+                        if name.kind == TokenKind::Ident && equals.text(text) == "=" =>
+                    {
+                        Some(name.text(text).to_string())
+                    }
+                    _ => None,
+                };
+                if open == "(" && i > 0 {
+                    calls.push(Call {
+                        callee_end: before[i - 1].end,
+                        index,
+                        named,
+                    });
+                }
+                index = 0;
+                argument = None;
+            }
+            _ => {}
+        }
+    }
+    calls
+}
+
+/// The signature of the function whose expression ends at `callee_end`,
+/// and the names of its parameters.
+fn signature(
+    resolver: &Resolver,
+    root: &Expression,
+    callee_end: usize,
+) -> Option<(SignatureInformation, Vec<String>)> {
+    let callee = cst::descendants(&root.expr)
+        .into_iter()
+        .filter(|expr| scope::end(expr) == callee_end && scope::start(expr) < callee_end)
+        .min_by_key(|expr| scope::start(expr))?;
+    if let Expr::Index(target, index) = &*callee.0 {
+        if let (Expr::Var(var), Expr::Str(name)) = (&*target.0, &*index.0) {
+            if &**var == "std" {
+                let function = stdlib::find(name)?;
+                let params: Vec<String> = function.params?.iter().map(|p| p.to_string()).collect();
+                let names = params
+                    .iter()
+                    .map(|param| param.split('=').next().unwrap_or_default().to_string())
+                    .collect();
+                let mut signature = information(&format!("std.{}", name), &params);
+                signature.documentation = Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: function.doc.to_string(),
+                }));
+                return Some((signature, names));
+            }
+        }
+    }
+
+    let (params, body) = resolver.function(&root.with(callee.clone()))?;
+    let text = &body.document.text;
+    let names: Vec<String> = params.iter().map(|param| param.0.to_string()).collect();
+    let params: Vec<String> = params
+        .iter()
+        .map(|param| match &param.1 {
+            Some(default) => format!(
+                "{}={}",
+                param.0,
+                &text[scope::start(default)..scope::end(default)]
+            ),
+            None => param.0.to_string(),
+        })
+        .collect();
+    let name = &root.document.text[scope::start(callee)..callee_end];
+    Some((information(name, &params), names))
+}
+
+/// `name(a, b=1)` with the offsets of each parameter in it.
+fn information(name: &str, params: &[String]) -> SignatureInformation {
+    let mut label = format!("{}(", name);
+    let mut parameters = vec![];
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            label.push_str(", ");
+        }
+        let start = label.encode_utf16().count() as u32;
+        label.push_str(param);
+        let end = label.encode_utf16().count() as u32;
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, end]),
+            documentation: None,
+        });
+    }
+    label.push(')');
+    SignatureInformation {
+        label,
+        documentation: None,
+        parameters: Some(parameters),
+        active_parameter: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::{ParameterLabel, Url};
+
+    use crate::{document::Document, files::Files, testing::TempDir, utils};
+
+    /// The signature label at `|` in `code` and the active parameter.
+    fn help(files: &mut Files, uri: &Url, code: &str) -> Option<(String, Option<String>)> {
+        let offset = code.find('|').unwrap();
+        let code = code.replacen('|', "", 1);
+        files.insert(uri.clone(), Document::new(uri, code.clone()));
+        let position = utils::offset_to_position(&code, offset);
+        let help = super::help(files, uri, position)?;
+        let signature = help.signatures.into_iter().next()?;
+        let active = help.active_parameter.map(|index| {
+            match &signature.parameters.as_ref().unwrap()[index as usize].label {
+                ParameterLabel::LabelOffsets([start, end]) => {
+                    signature.label[*start as usize..*end as usize].to_string()
+                }
+                ParameterLabel::Simple(label) => label.clone(),
+            }
+        });
+        Some((signature.label, active))
+    }
+
+    #[test]
+    fn locals_and_std() {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        let mut help = |code: &str| help(&mut files, &uri, code);
+        let f = "local f(a, b=1, c=[1, 2]) = a;\n";
+        let signature = || "f(a, b=1, c=[1, 2])".to_string();
+
+        assert_eq!(
+            help(&format!("{}f(|", f)),
+            Some((signature(), Some("a".to_string())))
+        );
+        assert_eq!(
+            help(&format!("{}f(1, [2, 3], |)", f)),
+            Some((signature(), Some("c=[1, 2]".to_string())))
+        );
+        assert_eq!(
+            help(&format!("{}f(1, c=|)", f)),
+            Some((signature(), Some("c=[1, 2]".to_string())))
+        );
+        assert_eq!(
+            help(&format!("{}f(1, 2, 3, |)", f)),
+            Some((signature(), None))
+        );
+        // The innermost call that is a function.
+        assert_eq!(
+            help(&format!("{}f((1 + |), 2)", f)),
+            Some((signature(), Some("a".to_string())))
+        );
+        assert_eq!(
+            help("std.map(function(x) x, |)"),
+            Some(("std.map(func, arr)".to_string(), Some("arr".to_string())))
+        );
+        assert_eq!(help("local g = 1; g(|)"), None);
+    }
+
+    #[test]
+    fn methods() {
+        let root = TempDir::new("signature");
+        fs::write(
+            root.join("lib.libsonnet"),
+            "{ new(name, replicas=1):: {}, util: { join: function(sep, parts) '' } }",
+        )
+        .unwrap();
+        let uri = Url::from_file_path(root.join("main.jsonnet")).unwrap();
+        let mut files = Files::new(vec![]);
+
+        let code = "local lib = import 'lib.libsonnet';\nlib.new('x', |)";
+        assert_eq!(
+            help(&mut files, &uri, code),
+            Some((
+                "lib.new(name, replicas=1)".to_string(),
+                Some("replicas=1".to_string())
+            ))
+        );
+        let code = "local lib = import 'lib.libsonnet';\nlib.util.join(|)";
+        assert_eq!(
+            help(&mut files, &uri, code),
+            Some((
+                "lib.util.join(sep, parts)".to_string(),
+                Some("sep".to_string())
+            ))
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Documentation of the standard library, bundled with the server.
+//!
+//! Only the functions the evaluator actually provides are offered, see
+//! [`functions`].
+
+use std::{collections::HashSet, path::PathBuf, rc::Rc};
+
+use jrsonnet_evaluator::EvaluationState;
+use log::warn;
+
+pub struct Function {
+    pub name: &'static str,
+    /// Parameters, optional ones with their default like `keyF=id`. `None`
+    /// for fields that aren't functions, like `thisFile`.
+    pub params: Option<&'static [&'static str]>,
+    pub doc: &'static str,
+}
+
+impl Function {
+    pub fn signature(&self) -> String {
+        match self.params {
+            Some(params) => format!("std.{}({})", self.name, params.join(", ")),
+            None => format!("std.{}", self.name),
+        }
+    }
+
+    /// A snippet that calls the function with placeholders for the
+    /// required parameters.
+    pub fn snippet(&self) -> String {
+        let params = match self.params {
+            Some(params) => params,
+            None => return self.name.to_string(),
+        };
+        let placeholders: Vec<String> = params
+            .iter()
+            .filter(|param| !param.contains('='))
+            .enumerate()
+            .map(|(i, param)| format!("${{{}:{}}}", i + 1, param))
+            .collect();
+        format!("{}({})", self.name, placeholders.join(", "))
+    }
+}
+
+thread_local! {
+    static AVAILABLE: Option<HashSet<String>> = available();
+}
+
+/// The names of the fields of `std` in the evaluator, including hidden ones.
+fn available() -> Option<HashSet<String>> {
+    let state = EvaluationState::default();
+    state.with_stdlib();
+    let manifested = state.run_in_state(|| {
+        let fields = state.evaluate_snippet_raw(
+            Rc::new(PathBuf::from("<stdlib>")),
+            "std.objectFieldsAll(std)".into(),
+        )?;
+        state.manifest(fields)
+    });
+    let names = manifested
+        .map_err(|err| err.error().to_string())
+        .and_then(|json| serde_json::from_str::<Vec<String>>(&json).map_err(|err| err.to_string()));
+    match names {
+        Ok(names) => Some(names.into_iter().collect()),
+        Err(err) => {
+            warn!("Failed to list the fields of std: {}", err);
+            None
+        }
+    }
+}
+
+/// The std functions as the evaluator provides them: documented ones with
+/// their documentation, others only by name.
+pub fn functions() -> Vec<(String, Option<&'static Function>)> {
+    AVAILABLE.with(|available| match available {
+        Some(available) => {
+            let mut functions: Vec<_> = available
+                .iter()
+                .filter(|name| crate::utils::is_identifier(name))
+                .map(|name| (name.clone(), find(name)))
+                .collect();
+            functions.sort_by(|a, b| a.0.cmp(&b.0));
+            functions
+        }
+        None => FUNCTIONS
+            .iter()
+            .map(|function| (function.name.to_string(), Some(function)))
+            .collect(),
+    })
+}
+
+pub fn find(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+macro_rules! functions {
+    (@params) => { None };
+    (@params ($($param:literal),*)) => { Some(&[$($param),*]) };
+    ($($name:literal $(($($param:literal),*))? $doc:literal,)*) => {
+        pub const FUNCTIONS: &[Function] = &[
+            $(Function {
+                name: $name,
+                params: functions!(@params $(($($param),*))?),
+                doc: $doc,
+            },)*
+        ];
+    };
+}
+
+functions! {
+    "thisFile" "Note that this is a field. It contains the current Jsonnet filename as a string.",
+    "native"("name") "Returns the native function that was registered with the evaluator under the given name.",
+    "extVar"("x") "If an external variable with the given name was defined, return its string value. Otherwise, raise an error.",
+    "type"("x") "Return a string that indicates the type of the value. The possible return values are `\"array\"`, `\"boolean\"`, `\"function\"`, `\"null\"`, `\"number\"`, `\"object\"`, and `\"string\"`.",
+    "length"("x") "Depending on the type of the value given, either returns the number of elements in the array, the number of codepoints in the string, the number of parameters in the function, or the number of fields in the object. Raises an error if given a primitive value, i.e. `null`, `true` or `false`.",
+    "objectHas"("o", "f") "Returns `true` if the given object has the field (given as a string), otherwise `false`. Raises an error if the arguments are not object and string respectively. Returns false if the field is hidden.",
+    "objectFields"("o") "Returns an array of strings, each element being a field from the given object. Does not include hidden fields.",
+    "objectValues"("o") "Returns an array of the values in the given object. Does not include hidden fields.",
+    "objectHasAll"("o", "f") "As `std.objectHas` but also includes hidden fields.",
+    "objectFieldsAll"("o") "As `std.objectFields` but also includes hidden fields.",
+    "objectValuesAll"("o") "As `std.objectValues` but also includes hidden fields.",
+    "objectFieldsEx"("obj", "hidden") "As `std.objectFields` but also includes hidden fields if `hidden` is `true`.",
+    "objectHasEx"("obj", "f", "hidden") "As `std.objectHas` but also considers hidden fields if `hidden` is `true`.",
+    "prune"("a") "Recursively remove all \"empty\" members of `a`. \"Empty\" is defined as zero length arrays, zero length objects, or `null` values. The argument `a` may have any type.",
+    "mapWithKey"("func", "obj") "Apply the given function to all fields of the given object, also passing the field name. The function `func` is expected to take the field name as the first parameter and the field value as the second.",
+    "isArray"("v") "Returns `true` if the value is an array.",
+    "isBoolean"("v") "Returns `true` if the value is a boolean.",
+    "isFunction"("v") "Returns `true` if the value is a function.",
+    "isNumber"("v") "Returns `true` if the value is a number.",
+    "isObject"("v") "Returns `true` if the value is an object.",
+    "isString"("v") "Returns `true` if the value is a string.",
+    "abs"("n") "Returns the absolute value of `n`.",
+    "sign"("n") "Returns `-1`, `0` or `1` depending on the sign of `n`.",
+    "max"("a", "b") "Returns the larger of `a` and `b`.",
+    "min"("a", "b") "Returns the smaller of `a` and `b`.",
+    "modulo"("a", "b") "Returns the remainder of dividing the number `a` by the number `b`.",
+    "mod"("a", "b") "Implements the `%` operator: the remainder of `a / b` if both are numbers, `std.format(a, b)` if `a` is a string.",
+    "pow"("x", "n") "Returns `x` raised to the power of `n`.",
+    "exp"("x") "Returns e raised to the power of `x`.",
+    "log"("x") "Returns the natural logarithm of `x`.",
+    "exponent"("x") "Returns the exponent of the IEEE754 64 bit floating point representation of `x`.",
+    "mantissa"("x") "Returns the mantissa of the IEEE754 64 bit floating point representation of `x`.",
+    "floor"("x") "Returns the largest integer not greater than `x`.",
+    "ceil"("x") "Returns the smallest integer not less than `x`.",
+    "sqrt"("x") "Returns the square root of `x`.",
+    "sin"("x") "Returns the sine of `x`, in radians.",
+    "cos"("x") "Returns the cosine of `x`, in radians.",
+    "tan"("x") "Returns the tangent of `x`, in radians.",
+    "asin"("x") "Returns the arc sine of `x`, in radians.",
+    "acos"("x") "Returns the arc cosine of `x`, in radians.",
+    "atan"("x") "Returns the arc tangent of `x`, in radians.",
+    "clamp"("x", "minVal", "maxVal") "Clamp a value to fit within the range [`minVal`, `maxVal`]. Equivalent to `std.max(minVal, std.min(x, maxVal))`.",
+    "primitiveEquals"("a", "b") "Returns whether the primitive values `a` and `b` are equal. Raises an error if either is an array, an object or a function.",
+    "equals"("a", "b") "Implements the `==` operator: returns whether `a` and `b` are equal, comparing arrays and objects deeply.",
+    "assertEqual"("a", "b") "Ensure that `a == b`. Returns `true` or throws an error message.",
+    "id"("x") "Returns `x` unchanged.",
+    "toString"("a") "Convert the given argument to a string.",
+    "codepoint"("str") "Returns the positive integer representing the unicode codepoint of the character in the given single-character string. This function is the inverse of `std.char(n)`.",
+    "char"("n") "Returns a string of length one whose only unicode codepoint has integer id `n`. This function is the inverse of `std.codepoint(str)`.",
+    "substr"("str", "from", "len") "Returns a string that is the part of `str` that starts at offset `from` and is `len` codepoints long. If the string `str` is shorter than `from + len`, the suffix starting at position `from` will be returned.",
+    "findSubstr"("pat", "str") "Returns an array that contains the indexes of all occurrences of `pat` in `str`.",
+    "startsWith"("a", "b") "Returns whether the string `a` is prefixed by the string `b`.",
+    "endsWith"("a", "b") "Returns whether the string `a` is suffixed by the string `b`.",
+    "stripChars"("str", "chars") "Removes characters `chars` from the beginning and from the end of `str`.",
+    "lstripChars"("str", "chars") "Removes characters `chars` from the beginning of `str`.",
+    "rstripChars"("str", "chars") "Removes characters `chars` from the end of `str`.",
+    "split"("str", "c") "Split the string `str` into an array of strings, divided by the string `c`.",
+    "splitLimit"("str", "c", "maxsplits") "As `std.split(str, c)` but will stop after `maxsplits` splits, thereby the largest array it will return has length `maxsplits + 1`. A limit of `-1` means unlimited.",
+    "strReplace"("str", "from", "to") "Returns a copy of the string in which all occurrences of string `from` have been replaced with string `to`.",
+    "asciiUpper"("str") "Returns a copy of the string in which all ASCII letters are capitalized.",
+    "asciiLower"("str") "Returns a copy of the string in which all ASCII letters are lower cased.",
+    "stringChars"("str") "Split the string `str` into an array of strings, each containing a single codepoint.",
+    "format"("str", "vals") "Format the string `str` using the values in `vals`. The values can be an array, an object, or in other cases are treated as if they were provided in a singleton array. The string formatting follows the same rules as Python. The `%` operator can be used as a shorthand for this function.",
+    "escapeStringBash"("str") "Wrap `str` in single quotes, and escape any single quotes within `str` by changing them to a sequence `'\"'\"'`. This allows injection of arbitrary strings as arguments of commands in bash scripts.",
+    "escapeStringDollars"("str") "Convert `$` to `$$` in `str`. This allows injection of arbitrary strings into systems that use `$` for string interpolation (like Terraform).",
+    "escapeStringJson"("str") "Convert `str` to allow it to be embedded in a JSON representation, within a string. This adds quotes, escapes backslashes, and escapes unprintable characters.",
+    "escapeStringPython"("str") "Convert `str` to allow it to be embedded in Python. This is an alias for `std.escapeStringJson`.",
+    "parseInt"("str") "Parses a signed decimal integer from the input string.",
+    "parseOctal"("str") "Parses an unsigned octal integer from the input string. Initial zeroes are tolerated.",
+    "parseHex"("str") "Parses an unsigned hexadecimal integer, from the input string. Case insensitive.",
+    "parseJson"("str") "Parses a JSON string.",
+    "encodeUTF8"("str") "Encode a string using UTF8. Returns an array of numbers representing bytes.",
+    "decodeUTF8"("arr") "Decode an array of numbers representing bytes using UTF8. Returns a string.",
+    "manifestIni"("ini") "Convert the given structure to a string in INI format. This allows using Jsonnet's object model to build a configuration to be consumed by an application expecting an INI file.",
+    "manifestPython"("v") "Convert the given value to a JSON-like form that is compatible with Python. The chief differences are True / False / None instead of true / false / null.",
+    "manifestPythonVars"("conf") "Convert the given object to a JSON-like form that is compatible with Python. The key difference to `std.manifestPython` is that the top level is represented as a list of Python global variables.",
+    "manifestJson"("value") "Convert the given object to a JSON form. Under the covers, it calls `std.manifestJsonEx` with a 4-space indent.",
+    "manifestJsonEx"("value", "indent") "Convert the given object to a JSON form. `indent` is a string containing one or more whitespaces that are used for indentation.",
+    "manifestToml"("toml") "Convert the given object to a TOML form. Under the covers, it calls `std.manifestTomlEx` with a 2-space indent.",
+    "manifestTomlEx"("toml", "indent") "Convert the given object to a TOML form. `indent` is a string containing one or more whitespaces that are used for indentation.",
+    "manifestYamlDoc"("value", "indent_array_in_object=false") "Convert the given value to a YAML form. Note that `std.manifestJson` could also be used for this purpose, because any JSON is also valid YAML. But this function will produce more canonical-looking YAML.",
+    "manifestYamlStream"("value", "indent_array_in_object=false", "c_document_end=true") "Given an array of values, emit a YAML \"stream\", which is a sequence of documents separated by `---` and ending with `...`.",
+    "manifestXmlJsonml"("value") "Convert the given JsonML-encoded value to a string containing the XML.",
+    "makeArray"("sz", "func") "Create a new array of `sz` elements by calling `func(i)` to initialize each element. `func` is expected to be a function that takes a single parameter, the index of the element it should initialize.",
+    "member"("arr", "x") "Returns whether `x` occurs in `arr`. Argument `arr` may be an array or a string.",
+    "count"("arr", "x") "Return the number of times that `x` occurs in `arr`.",
+    "find"("value", "arr") "Returns an array that contains the indexes of all occurrences of `value` in `arr`.",
+    "map"("func", "arr") "Apply the given function to every element of the array to form a new array.",
+    "mapWithIndex"("func", "arr") "Similar to `std.map`, but it also passes to the function the element's index in the array. The function `func` is expected to take the index as the first parameter and the element as the second.",
+    "filterMap"("filter_func", "map_func", "arr") "It first filters, then maps the given array, using the two functions provided.",
+    "flatMap"("func", "arr") "Apply the given function to every element of `arr` to form a new array then flatten the result. The argument `arr` must be an array or a string. If `arr` is an array, function `func` must return an array. If `arr` is a string, function `func` must return a string.",
+    "filter"("func", "arr") "Return a new array containing all the elements of `arr` for which the `func` function returns true.",
+    "foldl"("func", "arr", "init") "Classic foldl function. Calls the function `func` on the result of the previous function call and each array element, or `init` in the case of the initial element. Traverses the array from left to right.",
+    "foldr"("func", "arr", "init") "Classic foldr function. Calls the function `func` on each array element and the result of the previous function call, or `init` in the case of the initial element. Traverses the array from right to left.",
+    "range"("from", "to") "Return an array of ascending numbers between the two limits, inclusively.",
+    "repeat"("what", "count") "Repeats an array or a string `what` a number of times specified by an integer `count`.",
+    "slice"("indexable", "index", "end", "step") "Selects the elements of an array or a string from `index` to `end` with `step` and returns an array or a string respectively. Note that it's recommended to use dedicated slicing syntax both for arrays and strings (e.g. `arr[0:4:1]` instead of `std.slice(arr, 0, 4, 1)`).",
+    "join"("sep", "arr") "If `sep` is a string, then `arr` must be an array of strings, in which case they are concatenated with `sep` used as a delimiter. If `sep` is an array, then `arr` must be an array of arrays, in which case the arrays are concatenated in the same way, to produce a single array.",
+    "deepJoin"("arr") "Concatenate an array containing strings and arrays to form a single string. If `arr` is a string, it is returned unchanged. If it is an array, it is flattened and the string elements are concatenated together with no separator.",
+    "lines"("arr") "Concatenate an array of strings into a text file with newline characters after each string. This is suitable for constructing bash scripts and the like.",
+    "flattenArrays"("arr") "Concatenate an array of arrays into a single array.",
+    "reverse"("arrs") "Reverse an array.",
+    "sort"("arr", "keyF=id") "Sorts the array using the <= operator. Optional argument `keyF` is a single argument function used to extract comparison key from each array element.",
+    "uniq"("arr", "keyF=id") "Removes successive duplicates. When given a sorted array, removes all duplicates. Optional argument `keyF` is a single argument function used to extract comparison key from each array element.",
+    "all"("arr") "Return true if all elements of the input array are true, false otherwise. `all([])` evaluates to true.",
+    "any"("arr") "Return true if any element of the input array is true, false otherwise. `any([])` evaluates to false.",
+    "set"("arr", "keyF=id") "Shortcut for `std.uniq(std.sort(arr))`.",
+    "setInter"("a", "b", "keyF=id") "Set intersection operation (values in both `a` and `b`).",
+    "setUnion"("a", "b", "keyF=id") "Set union operation (values in any of `a` or `b`). Note that `+` on sets will simply concatenate the arrays, possibly forming an array that is not a set (due to not being ordered without duplicates).",
+    "setDiff"("a", "b", "keyF=id") "Set difference operation (values in `a` but not `b`).",
+    "setMember"("x", "arr", "keyF=id") "Returns `true` if `x` is a member of array, otherwise `false`.",
+    "base64"("input") "Encodes the given value into a base64 string. The encoding sequence is `A-Za-z0-9+/` with `=` to pad the output to a multiple of 4 characters. The value can be a string or an array of numbers, but the codepoints / numbers must be in the 0 to 255 range.",
+    "base64DecodeBytes"("str") "Decodes the given base64 string into an array of bytes (number values). Currently assumes the input string has no linebreaks and is padded to a multiple of 4.",
+    "base64Decode"("str") "Deprecated, use `std.base64DecodeBytes` and decode the string explicitly instead. Behaves like `std.base64DecodeBytes` except returns a naively encoded string instead of an array of bytes.",
+    "md5"("s") "Encodes the given value into an MD5 string.",
+    "mergePatch"("target", "patch") "Applies `patch` to `target` according to RFC7396.",
+    "trace"("str", "rest") "Outputs the given string `str` to stderr and returns `rest` as the result.",
+    "resolvePath"("f", "r") "Replaces the last segment of the path `f` with `r`, e.g. `std.resolvePath('a/b.jsonnet', 'c.libsonnet')` is `'a/c.libsonnet'`.",
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn snippets() {
+        let sort = super::find("sort").unwrap();
+        assert_eq!(sort.signature(), "std.sort(arr, keyF=id)");
+        assert_eq!(sort.snippet(), "sort(${1:arr})");
+        let type_function = super::find("type").unwrap();
+        assert_eq!(type_function.snippet(), "type(${1:x})");
+        let this_file = super::find("thisFile").unwrap();
+        assert_eq!(this_file.signature(), "std.thisFile");
+        assert_eq!(this_file.snippet(), "thisFile");
+    }
+
+    #[test]
+    fn every_field_documented() {
+        let available = super::available().expect("the evaluator lists the fields of std");
+        let mut missing: Vec<_> = available
+            .iter()
+            .filter(|name| crate::utils::is_identifier(name) && super::find(name).is_none())
+            .collect();
+        missing.sort();
+        assert!(missing.is_empty(), "undocumented std fields: {:?}", missing);
+    }
+}
@@ -0,0 +1,335 @@
+//! Document symbols: an outline of the locals, fields, parameters and
+//! asserts of a document, and the symbols it contributes to the workspace
+//! index.
+
+use std::{iter::Peekable, vec};
+
+use jrsonnet_parser::{
+    AssertStmt, BinaryOpType, Expr, LiteralType, LocExpr, Member, ObjBody, ParamsDesc,
+};
+use lsp_types::{DocumentSymbol, Location, SymbolInformation, SymbolKind, Url};
+
+use crate::{
+    cst::{self, TokenKind},
+    document::Document,
+    fields::{Expression, Object},
+    files::Files,
+    scope::{self, Kind},
+    utils,
+};
+
+/// A symbol before it is nested into its parent.
+struct Item {
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    /// Whether the item is a local or field. Those go into the workspace
+    /// index, unless they are inside a function.
+    indexed: bool,
+    /// Byte range of the whole definition.
+    start: usize,
+    end: usize,
+    /// Byte range of the name.
+    name_start: usize,
+    name_end: usize,
+}
+
+struct Node {
+    item: Item,
+    children: Vec<Node>,
+}
+
+pub fn document(files: &Files, uri: &Url) -> Vec<DocumentSymbol> {
+    let document = match files.get(uri) {
+        Some(document) => document,
+        None => return vec![],
+    };
+    let root = Expression {
+        uri: uri.clone(),
+        expr: document.ast.clone(),
+        document: document.clone(),
+    };
+    let text = &document.text;
+    fn convert(text: &str, node: Node) -> DocumentSymbol {
+        let Node { item, children } = node;
+        let children: Vec<_> = children
+            .into_iter()
+            .map(|child| convert(text, child))
+            .collect();
+        #[allow(deprecated)]
+        DocumentSymbol {
+            name: item.name,
+            detail: item.detail,
+            kind: item.kind,
+            tags: None,
+            deprecated: None,
+            range: utils::offset_range_to_range(text, item.start, item.end),
+            selection_range: utils::offset_range_to_range(text, item.name_start, item.name_end),
+            children: if children.is_empty() {
+                None
+            } else {
+                Some(children)
+            },
+        }
+    }
+    outline(&root)
+        .into_iter()
+        .map(|node| convert(text, node))
+        .collect()
+}
+
+/// The locals and fields of a document that are not inside a function,
+/// each with the name of the symbol containing it.
+pub fn index(root: &Expression) -> Vec<SymbolInformation> {
+    fn walk(
+        root: &Expression,
+        nodes: Vec<Node>,
+        container: Option<&str>,
+        out: &mut Vec<SymbolInformation>,
+    ) {
+        for Node { item, children } in nodes {
+            if !item.indexed {
+                continue;
+            }
+            #[allow(deprecated)]
+            out.push(SymbolInformation {
+                name: item.name.clone(),
+                kind: item.kind,
+                tags: None,
+                deprecated: None,
+                location: Location {
+                    uri: root.uri.clone(),
+                    range: utils::offset_range_to_range(
+                        &root.document.text,
+                        item.name_start,
+                        item.name_end,
+                    ),
+                },
+                container_name: container.map(str::to_string),
+            });
+            if !matches!(item.kind, SymbolKind::Function | SymbolKind::Method) {
+                walk(root, children, Some(&item.name), out);
+            }
+        }
+    }
+    let mut symbols = vec![];
+    walk(root, outline(root), None, &mut symbols);
+    symbols
+}
+
+fn outline(root: &Expression) -> Vec<Node> {
+    let mut items = items(root);
+    items.sort_by_key(|item| (item.start, std::cmp::Reverse(item.end)));
+    nest(&mut items.into_iter().peekable(), usize::MAX)
+}
+
+/// The items whose range starts before `end`, with the items they contain
+/// as children.
+fn nest(items: &mut Peekable<vec::IntoIter<Item>>, end: usize) -> Vec<Node> {
+    let mut nodes = vec![];
+    while let Some(item) = items.next_if(|item| item.start < end) {
+        let children = nest(items, item.end);
+        nodes.push(Node { item, children });
+    }
+    nodes
+}
+
+fn items(root: &Expression) -> Vec<Item> {
+    let document = &root.document;
+    let mut items = vec![];
+
+    let analysis = scope::analyze(document);
+    for definition in &analysis.definitions {
+        let (kind, detail, end, indexed) = match (definition.kind, &definition.value) {
+            (Kind::Local, Some(value)) => {
+                let (kind, detail) = match &definition.params {
+                    Some(params) => (SymbolKind::Function, Some(params_detail(params))),
+                    None => (value_kind(value).unwrap_or(SymbolKind::Variable), None),
+                };
+                (kind, detail, scope::end(value), true)
+            }
+            (Kind::Parameter, _) => (SymbolKind::Variable, None, definition.end, false),
+            _ => continue,
+        };
+        items.push(Item {
+            name: definition.name.clone(),
+            detail,
+            kind,
+            indexed,
+            start: definition.start,
+            end,
+            name_start: definition.start,
+            name_end: definition.end,
+        });
+    }
+
+    for expr in cst::descendants(&root.expr) {
+        let members = match &*expr.0 {
+            Expr::AssertExpr(assert, _) => {
+                items.extend(assert_item(document, assert));
+                continue;
+            }
+            Expr::Obj(ObjBody::MemberList(members))
+            | Expr::ObjExtend(_, ObjBody::MemberList(members)) => members,
+            _ => continue,
+        };
+        for member in members {
+            if let Member::AssertStmt(assert) = member {
+                items.extend(assert_item(document, assert));
+            }
+        }
+        let object = Object {
+            layers: vec![root.with(expr.clone())],
+        };
+        for field in object.fields() {
+            let member = field.member();
+            let (kind, detail) = match &member.params {
+                Some(params) => (SymbolKind::Method, Some(params_detail(params))),
+                None => (value_kind(&member.value).unwrap_or(SymbolKind::Field), None),
+            };
+            items.push(Item {
+                name: field.name().to_string(),
+                detail,
+                kind,
+                indexed: true,
+                start: field.start,
+                end: scope::end(&member.value),
+                name_start: field.start,
+                name_end: field.end,
+            });
+        }
+    }
+    items
+}
+
+/// `assert cond : message`, named after its condition.
+fn assert_item(document: &Document, assert: &AssertStmt) -> Option<Item> {
+    let text = &document.text;
+    let AssertStmt(cond, message) = assert;
+    let cond_start = scope::start(cond);
+    let keyword = document
+        .cst
+        .tokens
+        .iter()
+        .take_while(|t| t.start < cond_start)
+        .filter(|t| t.kind == TokenKind::Keyword && t.text(text) == "assert")
+        .last()?;
+    let condition = text[cond_start..scope::end(cond)].trim();
+    Some(Item {
+        name: format!("assert {}", condition.lines().next().unwrap_or_default()),
+        detail: None,
+        kind: SymbolKind::Event,
+        indexed: false,
+        start: keyword.start,
+        end: scope::end(message.as_ref().unwrap_or(cond)),
+        name_start: keyword.start,
+        name_end: keyword.end,
+    })
+}
+
+fn params_detail(params: &ParamsDesc) -> String {
+    let params: Vec<&str> = params.iter().map(|param| &*param.0).collect();
+    format!("({})", params.join(", "))
+}
+
+/// The symbol kind matching the value of `expr`, if it can be told from
+/// the syntax.
+fn value_kind(expr: &LocExpr) -> Option<SymbolKind> {
+    match &*expr.0 {
+        Expr::Obj(_) | Expr::ObjExtend(..) => Some(SymbolKind::Object),
+        Expr::Function(..) => Some(SymbolKind::Function),
+        Expr::Import(_) => Some(SymbolKind::Module),
+        Expr::Str(_) | Expr::ImportStr(_) => Some(SymbolKind::String),
+        Expr::Num(_) => Some(SymbolKind::Number),
+        Expr::Arr(_) | Expr::ArrComp(..) => Some(SymbolKind::Array),
+        Expr::Literal(LiteralType::True | LiteralType::False) => Some(SymbolKind::Boolean),
+        Expr::Literal(LiteralType::Null) => Some(SymbolKind::Null),
+        Expr::Parened(inner) | Expr::LocalExpr(_, inner) | Expr::AssertExpr(_, inner) => {
+            value_kind(inner)
+        }
+        Expr::BinaryOp(left, BinaryOpType::Add, right) => {
+            match (value_kind(left), value_kind(right)) {
+                (Some(SymbolKind::Object), _) | (_, Some(SymbolKind::Object)) => {
+                    Some(SymbolKind::Object)
+                }
+                (Some(SymbolKind::String), _) | (_, Some(SymbolKind::String)) => {
+                    Some(SymbolKind::String)
+                }
+                (Some(SymbolKind::Array), _) | (_, Some(SymbolKind::Array)) => {
+                    Some(SymbolKind::Array)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{DocumentSymbol, Url};
+
+    use crate::{document::Document, files::Files};
+
+    /// The symbols as `name kind` lines, indented by depth.
+    fn outline(symbols: &[DocumentSymbol], depth: usize, out: &mut Vec<String>) {
+        for symbol in symbols {
+            out.push(format!(
+                "{}{} {:?}",
+                "  ".repeat(depth),
+                symbol.name,
+                symbol.kind
+            ));
+            outline(
+                symbol.children.as_deref().unwrap_or_default(),
+                depth + 1,
+                out,
+            );
+        }
+    }
+
+    #[test]
+    fn nested_outline() {
+        let code = r#"local util = import 'util.libsonnet';
+local name = 'app';
+local deployment(replicas, image='nginx') = {
+  spec: { replicas: replicas },
+};
+{
+  assert std.length(name) > 0 : 'name is required',
+  local ports = [80],
+  app: deployment(3) + { metadata: { name: name } },
+  enabled:: true,
+  ports: ports,
+  labels(extra={}):: { app: name } + extra,
+}
+"#;
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        let mut files = Files::new(vec![]);
+        files.insert(uri.clone(), Document::new(&uri, code.to_string()));
+        let mut lines = vec![];
+        outline(&super::document(&files, &uri), 0, &mut lines);
+        assert_eq!(
+            lines,
+            [
+                "util Module",
+                "name String",
+                "deployment Function",
+                "  replicas Variable",
+                "  image Variable",
+                "  spec Object",
+                "    replicas Field",
+                "assert std.length(name) > 0 Event",
+                "ports Array",
+                "app Object",
+                "  metadata Object",
+                "    name Field",
+                "enabled Boolean",
+                "ports Field",
+                "labels Method",
+                "  extra Variable",
+                "  app Field",
+            ]
+        );
+    }
+}
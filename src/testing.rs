@@ -0,0 +1,36 @@
+//! Helpers shared by the tests.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A directory below the system's temporary directory that is removed when
+/// dropped, also when an assertion fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty directory named after `name` and the process, so
+    /// test runs in parallel do not share one.
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("jsonnet-ls-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
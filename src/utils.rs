@@ -1,9 +1,99 @@
 use lsp_types;
 
-use jrsonnet_evaluator;
 use jrsonnet_parser;
 use jrsonnet_parser::peg::str::LineCol;
 
+use crate::{document::Document, scope};
+
+use std::{path::PathBuf, rc::Rc};
+
+pub const KEYWORDS: &[&str] = &[
+    "assert",
+    "else",
+    "error",
+    "false",
+    "for",
+    "function",
+    "if",
+    "import",
+    "importstr",
+    "in",
+    "local",
+    "null",
+    "self",
+    "super",
+    "tailstrict",
+    "then",
+    "true",
+];
+
+/// Returns true if `name` can be written as a bare identifier, e.g. as an
+/// object field name or after a `.` index.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric()) && !KEYWORDS.contains(&name)
+}
+
+/// Parser settings that keep expression locations, which every position
+/// based feature relies on.
+pub fn parser_settings(uri: &lsp_types::Url) -> jrsonnet_parser::ParserSettings {
+    let path = uri
+        .to_file_path()
+        .unwrap_or_else(|_| PathBuf::from(uri.path()));
+    jrsonnet_parser::ParserSettings {
+        loc_data: true,
+        file_name: Rc::new(path),
+    }
+}
+
+/// Converts a byte offset into an LSP position, counting characters in
+/// UTF-16 code units as the protocol requires.
+pub fn offset_to_position(code: &str, offset: usize) -> lsp_types::Position {
+    let offset = offset.min(code.len());
+    let line_start = code[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = code[..line_start].matches('\n').count();
+    let character: usize = code[line_start..offset].chars().map(char::len_utf16).sum();
+    lsp_types::Position {
+        line: line as u32,
+        character: character as u32,
+    }
+}
+
+/// Converts an LSP position into a byte offset, clamping to the end of the
+/// line or document.
+pub fn position_to_offset(code: &str, position: lsp_types::Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match code[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return code.len(),
+        }
+    }
+    let line_end = code[line_start..]
+        .find('\n')
+        .map(|i| line_start + i)
+        .unwrap_or_else(|| code.len());
+    let mut character = 0;
+    for (i, c) in code[line_start..line_end].char_indices() {
+        if character >= position.character as usize {
+            return line_start + i;
+        }
+        character += c.len_utf16();
+    }
+    line_end
+}
+
+pub fn offset_range_to_range(code: &str, start: usize, end: usize) -> lsp_types::Range {
+    lsp_types::Range {
+        start: offset_to_position(code, start),
+        end: offset_to_position(code, end),
+    }
+}
+
 pub fn location_to_position(code: &str, line_col: &LineCol) -> lsp_types::Position {
     let lines = code.split('\n');
 
@@ -21,39 +111,59 @@ pub fn location_to_position(code: &str, line_col: &LineCol) -> lsp_types::Positi
     };
 }
 
-pub fn parse(text: &str) -> Vec<lsp_types::Diagnostic> {
-    let settings = jrsonnet_parser::ParserSettings::default();
-    let parsed = jrsonnet_parser::parse(&text, &settings);
-
-    let mut diagnostics = vec![];
-
-    match parsed {
-        Ok(ast) => {
-            let context = jrsonnet_evaluator::Context::new();
-            let _result = jrsonnet_evaluator::evaluate(context, &ast);
-        }
-        Err(err) => {
-            let position_start = location_to_position(text, &err.location);
-            let position_end = lsp_types::Position {
-                line: position_start.line,
-                character: position_start.character + 1,
-            };
-            diagnostics.push(lsp_types::Diagnostic {
-                range: lsp_types::Range {
-                    start: position_start,
-                    end: position_end,
-                },
-                severity: Some(lsp_types::DiagnosticSeverity::Error),
-                message: err.to_string(),
-                ..lsp_types::Diagnostic::default()
-            });
+/// Syntax errors of the document, as found when it was parsed.
+pub fn parse(document: &Document) -> Vec<lsp_types::Diagnostic> {
+    let text = &document.text;
+    // The reference parser stops at the first error, the tolerant one
+    // reports all of them.
+    let mut diagnostics: Vec<_> = document
+        .errors
+        .iter()
+        .map(|error| lsp_types::Diagnostic {
+            range: offset_range_to_range(text, error.start, error.end),
+            severity: Some(lsp_types::DiagnosticSeverity::Error),
+            message: error.message.clone(),
+            ..lsp_types::Diagnostic::default()
+        })
+        .collect();
+    if let (true, Err(err)) = (diagnostics.is_empty(), &document.parsed) {
+        // Names bound twice are rejected by the reference parser as well,
+        // the lints report them on each of the names.
+        let duplicates = scope::analyze(document)
+            .definitions
+            .iter()
+            .any(|definition| definition.duplicate);
+        if duplicates {
+            return diagnostics;
         }
-    };
-    return diagnostics;
+        let position_start = location_to_position(text, &err.location);
+        let position_end = lsp_types::Position {
+            line: position_start.line,
+            character: position_start.character + 1,
+        };
+        diagnostics.push(lsp_types::Diagnostic {
+            range: lsp_types::Range {
+                start: position_start,
+                end: position_end,
+            },
+            severity: Some(lsp_types::DiagnosticSeverity::Error),
+            message: err.to_string(),
+            ..lsp_types::Diagnostic::default()
+        });
+    }
+    diagnostics
 }
 
 #[cfg(test)]
 mod tests {
+    use lsp_types::{Diagnostic, Url};
+
+    use crate::document::Document;
+
+    fn parse(code: &str) -> Vec<Diagnostic> {
+        let uri = Url::parse("file:///test.jsonnet").unwrap();
+        super::parse(&Document::new(&uri, code.to_string()))
+    }
 
     #[test]
     fn parse_simple_jsonnet() {
@@ -65,7 +175,7 @@ mod tests {
     }
 "#;
 
-        let res = super::parse(&code);
+        let res = parse(code);
         assert_eq!(res, vec![]);
     }
 
@@ -78,7 +188,90 @@ mod tests {
       test3: 3,
     }
 "#;
-        let res = super::parse(&code);
-        assert_eq!(res, vec![]);
+        let res = parse(code);
+        let messages: Vec<_> = res
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(messages, vec![(4, "expected `,` or `}`, found `test3`")]);
+    }
+
+    #[test]
+    fn parse_reports_every_error() {
+        let code = r#"
+    {
+      test1: 1,
+      test2: 2.0
+      test3: 3,
+      test4: ,
+    }
+"#;
+        let res = parse(code);
+        let messages: Vec<_> = res
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (4, "expected `,` or `}`, found `test3`"),
+                (5, "expected expression, found `,`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_leaves_duplicates_to_lints() {
+        assert_eq!(parse("local a = 1, a = 2; a"), vec![]);
+        assert_eq!(parse("local f(x, x) = x; f(1, 2)"), vec![]);
+    }
+
+    #[test]
+    fn parsers_agree() {
+        let fixtures = [
+            "{ a: 1, b:: 'x', c+::: [1, 2.5, null], [if true then 'd']: true }",
+            "local f(x, y=2) = x + y; f(1) * f(x=3, y=4) % 5 - -6",
+            "local o = { local l = 1, a: l, assert self.a == 1 : 'msg' }; o { a+: 2 }.a",
+            "[x * y for x in [1, 2] if x > 1 for y in std.range(1, 3)]",
+            "{ [k]: v for k in ['a'] for v in [1] }",
+            "if std.isString($) then error 'no' else super.x[1:2:1]",
+            "import 'a.libsonnet' + importstr \"b.txt\" + |||\n  text\n|||",
+            "function(a) assert a != null; a.b['c'] == {} || !false && ~1 << 2 >= 3",
+            "// comment\n/* block */ { 'quoted': @'verbatim', \"s\": 1e3 } # hash",
+        ];
+        for code in &fixtures {
+            let uri = Url::parse("file:///test.jsonnet").unwrap();
+            let document = Document::new(&uri, code.to_string());
+            assert!(document.parsed.is_ok(), "reference parser rejects {}", code);
+            assert_eq!(document.errors.len(), 0, "tolerant parser rejects {}", code);
+        }
+    }
+
+    #[test]
+    fn offset_to_position() {
+        let code = "{\n  a: 'ü',\n  b: 2,\n}\n";
+        let offset = code.find('b').unwrap();
+        let position = super::offset_to_position(code, offset);
+        assert_eq!(
+            position,
+            lsp_types::Position {
+                line: 2,
+                character: 2
+            }
+        );
+        assert_eq!(super::position_to_offset(code, position), offset);
+
+        let after_umlaut = code.find("',").unwrap();
+        let position = super::offset_to_position(code, after_umlaut);
+        assert_eq!(position.character, 7);
+        assert_eq!(super::position_to_offset(code, position), after_umlaut);
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(super::is_identifier("foo_bar1"));
+        assert!(!super::is_identifier("1foo"));
+        assert!(!super::is_identifier("foo-bar"));
+        assert!(!super::is_identifier("local"));
     }
 }
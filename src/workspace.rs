@@ -0,0 +1,292 @@
+//! Index of the symbols of all Jsonnet files in the workspace and the
+//! library paths, for workspace symbol search. References to fields are
+//! looked for in the same files.
+
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use log::warn;
+use lsp_types::{SymbolInformation, Url};
+
+use crate::{document::Document, fields::Expression, files::Files, symbols};
+
+/// At most this many symbols are returned for a query.
+const MAX_RESULTS: usize = 256;
+
+pub struct Index {
+    /// Directories searched for Jsonnet files.
+    roots: Vec<PathBuf>,
+    /// Read on first use, as walking large workspaces takes a while.
+    files: RefCell<Option<HashMap<PathBuf, Vec<SymbolInformation>>>>,
+}
+
+impl Index {
+    /// An index of the Jsonnet files below `roots`. They are read when the
+    /// index is first used, afterwards it is kept up to date with
+    /// [`Index::update`].
+    pub fn new(roots: Vec<PathBuf>) -> Index {
+        Index {
+            roots,
+            files: RefCell::new(None),
+        }
+    }
+
+    /// Reads the file at `path` again after it was created, changed or
+    /// deleted. Files outside the roots are not indexed.
+    pub fn update(&mut self, path: &Path) {
+        if !is_jsonnet(path) || !self.roots.iter().any(|root| path.starts_with(root)) {
+            return;
+        }
+        // Not read yet, it is up to date once it is.
+        let files = match self.files.get_mut() {
+            Some(files) => files,
+            None => return,
+        };
+        let symbols = if path.is_file() {
+            read_symbols(path)
+        } else {
+            None
+        };
+        match symbols {
+            Some(symbols) => files.insert(path.to_path_buf(), symbols),
+            None => files.remove(path),
+        };
+    }
+
+    /// The paths of the indexed files.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files().keys().cloned().collect()
+    }
+
+    fn files(&self) -> Ref<'_, HashMap<PathBuf, Vec<SymbolInformation>>> {
+        if self.files.borrow().is_none() {
+            let mut paths = vec![];
+            for root in &self.roots {
+                jsonnet_files(root, &mut paths);
+            }
+            let files = paths
+                .into_iter()
+                .filter_map(|path| Some((path.clone(), read_symbols(&path)?)))
+                .collect();
+            *self.files.borrow_mut() = Some(files);
+        }
+        Ref::map(self.files.borrow(), |files| files.as_ref().unwrap())
+    }
+
+    /// The symbols whose name matches `query`, best matches first. Open
+    /// documents are searched in their edited version.
+    pub fn search(&self, files: &Files, query: &str) -> Vec<SymbolInformation> {
+        let mut open = HashMap::new();
+        for uri in files.opened() {
+            if let (Ok(path), Some(document)) = (uri.to_file_path(), files.get(uri)) {
+                let root = Expression {
+                    uri: uri.clone(),
+                    expr: document.ast.clone(),
+                    document,
+                };
+                open.insert(path, symbols::index(&root));
+            }
+        }
+        let files = self.files();
+        let indexed = files
+            .iter()
+            .filter(|(path, _)| !open.contains_key(*path))
+            .map(|(_, symbols)| symbols);
+        let mut matches: Vec<(i64, &SymbolInformation)> = indexed
+            .chain(open.values())
+            .flatten()
+            .filter_map(|symbol| Some((fuzzy_score(query, &symbol.name)?, symbol)))
+            .collect();
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| a.name.len().cmp(&b.name.len()))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        matches
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_, symbol)| symbol.clone())
+            .collect()
+    }
+}
+
+fn read_symbols(path: &Path) -> Option<Vec<SymbolInformation>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => {
+            warn!("Failed to read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    let uri = Url::from_file_path(path).ok()?;
+    let document = Rc::new(Document::new(&uri, text));
+    Some(symbols::index(&Expression {
+        uri,
+        expr: document.ast.clone(),
+        document,
+    }))
+}
+
+/// Directories below the roots that only hold dependencies of other tools.
+/// Jsonnet dependencies are found through the library paths, which are
+/// roots themselves.
+const SKIPPED: &[&str] = &["node_modules"];
+
+/// Collects the `.jsonnet` and `.libsonnet` files below `dir`. Hidden,
+/// [`SKIPPED`] and symlinked directories are skipped, the latter usually
+/// point into the library paths anyway.
+fn jsonnet_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let skipped = name.starts_with('.') || SKIPPED.contains(&name.as_str());
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() && !skipped => jsonnet_files(&path, out),
+            Ok(file_type) if !file_type.is_dir() && is_jsonnet(&path) => out.push(path),
+            _ => {}
+        }
+    }
+}
+
+fn is_jsonnet(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("jsonnet" | "libsonnet")
+    )
+}
+
+/// How well `name` matches `query`, if it contains all of its characters
+/// in order, ignoring case. Consecutive characters and ones at the start
+/// of a word score higher, skipped ones lower.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<i64> {
+    let name: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    for wanted in query.chars().map(|c| c.to_ascii_lowercase()) {
+        let found = (next..name.len()).find(|&i| name[i].to_ascii_lowercase() == wanted)?;
+        let word_start = found == 0
+            || name[found].is_ascii_uppercase()
+            || !name[found - 1].is_ascii_alphanumeric();
+        if found > 0 && found == next {
+            score += 5;
+        }
+        if word_start {
+            score += 3;
+        }
+        score += 1 - (found - next) as i64;
+        next = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::Url;
+
+    use super::{fuzzy_score, Index};
+    use crate::{document::Document, files::Files, testing::TempDir};
+
+    #[test]
+    fn fuzzy() {
+        assert!(fuzzy_score("newdep", "newDeployment").is_some());
+        assert!(fuzzy_score("NEWDEP", "newDeployment").is_some());
+        assert!(fuzzy_score("depnew", "newDeployment").is_none());
+        assert!(fuzzy_score("dep", "deployment") > fuzzy_score("dep", "newDeployment"));
+        assert!(fuzzy_score("nd", "newDeployment") > fuzzy_score("nd", "window"));
+    }
+
+    #[test]
+    fn search() {
+        let root = TempDir::new("workspace");
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("vendor/k")).unwrap();
+        fs::write(
+            root.join("vendor/k/k.libsonnet"),
+            "{ apps: { v1: { newDeployment(name):: { local inner = 1, name: name } } } }",
+        )
+        .unwrap();
+        fs::write(root.join("app/main.jsonnet"), "local deployment = 1; {}").unwrap();
+        fs::write(root.join("app/notes.txt"), "newDeployment").unwrap();
+        for skipped in &["app/.git", "app/node_modules/m"] {
+            fs::create_dir_all(root.join(skipped)).unwrap();
+            fs::write(
+                root.join(skipped).join("skipped.libsonnet"),
+                "{ skippedField: 1 }",
+            )
+            .unwrap();
+        }
+
+        let mut index = Index::new(vec![root.join("app"), root.join("vendor")]);
+        // Files are read on first use.
+        fs::write(root.join("app/late.jsonnet"), "{ lateField: 1 }").unwrap();
+        let mut files = Files::new(vec![]);
+        let search =
+            |index: &Index, files: &Files, query: &str| -> Vec<(String, Option<String>, String)> {
+                index
+                    .search(files, query)
+                    .into_iter()
+                    .map(|symbol| {
+                        let file = symbol
+                            .location
+                            .uri
+                            .path()
+                            .rsplit('/')
+                            .next()
+                            .unwrap()
+                            .to_string();
+                        (symbol.name, symbol.container_name, file)
+                    })
+                    .collect()
+            };
+
+        let found = search(&index, &files, "newdep");
+        assert_eq!(
+            found,
+            [(
+                "newDeployment".to_string(),
+                Some("v1".to_string()),
+                "k.libsonnet".to_string()
+            )]
+        );
+        // Nothing inside functions.
+        assert!(search(&index, &files, "inner").is_empty());
+        assert_eq!(search(&index, &files, "deployment")[0].0, "deployment");
+        assert_eq!(search(&index, &files, "lateField")[0].0, "lateField");
+        assert!(search(&index, &files, "skippedField").is_empty());
+
+        // Open documents take precedence over the file on disk.
+        let main = Url::from_file_path(root.join("app/main.jsonnet")).unwrap();
+        files.insert(
+            main.clone(),
+            Document::new(&main, "local service = 1; {}".to_string()),
+        );
+        assert_eq!(search(&index, &files, "deployment")[0].0, "newDeployment");
+        assert_eq!(search(&index, &files, "service")[0].0, "service");
+
+        // Files on disk are read again once they are reported as changed.
+        let added = root.join("app/added.libsonnet");
+        fs::write(&added, "{ addedField: 1 }").unwrap();
+        assert!(search(&index, &files, "addedField").is_empty());
+        index.update(&added);
+        assert_eq!(search(&index, &files, "addedField")[0].0, "addedField");
+        fs::remove_file(&added).unwrap();
+        index.update(&added);
+        assert!(search(&index, &files, "addedField").is_empty());
+        // Nothing outside the roots.
+        fs::write(root.join("outside.jsonnet"), "{ outsideField: 1 }").unwrap();
+        index.update(&root.join("outside.jsonnet"));
+        assert!(search(&index, &files, "outsideField").is_empty());
+    }
+}